@@ -0,0 +1,63 @@
+// Lexing benchmark. Generates Ink programs of 256 KB up to 4 MB and times
+// tokenizing each of them, to show that lexing time grows linearly with the
+// size of the program. Run with `cargo bench --bench lex`.
+
+use std::time::{Duration, Instant};
+
+use schrift::lex;
+
+const SIZES: [usize; 5] = [
+    256 * 1024,
+    512 * 1024,
+    1024 * 1024,
+    2048 * 1024,
+    4096 * 1024,
+];
+const RUNS: u32 = 5;
+
+// program generates an Ink program of at least `size` bytes, with a mix of
+// definitions, strings, comments and non-ASCII text
+fn program(size: usize) -> String {
+    let mut prog = String::with_capacity(size + 256);
+    let mut i = 0;
+    while prog.len() < size {
+        prog.push_str(&format!(
+            "` function number {i}, naïve and ünïcode `
+fn{i} := (größe, n) => n :: {{
+	0 -> 'zero: \\'{i}\\' ✓'
+	_ -> fn{i}(größe + {i}.5, n - 1)
+}}
+list{i} := [1, 2, 3, {{key: 'value', other: fn{i}}}]
+",
+            i = i
+        ));
+        i += 1;
+    }
+    return prog;
+}
+
+fn main() {
+    println!(
+        "{:>10}  {:>10}  {:>12}  {:>10}",
+        "bytes", "tokens", "time", "ns/byte"
+    );
+    for size in SIZES.iter() {
+        let prog = program(*size);
+        let mut tokens = 0;
+        let mut best = Duration::MAX;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            tokens = lex::tokenize(&prog)
+                .expect("generated program should lex")
+                .len();
+            best = best.min(start.elapsed());
+        }
+        println!(
+            "{:>10}  {:>10}  {:>12?}  {:>10.2}",
+            prog.len(),
+            tokens,
+            best,
+            best.as_nanos() as f64 / prog.len() as f64
+        );
+    }
+}
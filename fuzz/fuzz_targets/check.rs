@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use schrift::{check, runtime};
+
+// checks a program the way `schrift check` does, which runs the same front
+// end and name resolution as the language server
+fuzz_target!(|prog: &str| {
+    let _ = check::check(prog, runtime::builtins());
+});
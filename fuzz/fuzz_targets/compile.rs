@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use schrift::{debug, runtime};
+
+// compiles through every stage, including the optimizer and the verifier
+fuzz_target!(|prog: &str| {
+    let _ = debug::compile(prog, runtime::builtins());
+});
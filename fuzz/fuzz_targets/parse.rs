@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use schrift::{lex, parse};
+
+fuzz_target!(|prog: &str| {
+    if let Ok(tokens) = lex::tokenize(prog) {
+        let _ = parse::parse(tokens);
+    }
+});
@@ -0,0 +1,25 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use schrift::val::{NativeFn, Val};
+use schrift::vm::Vm;
+use schrift::{debug, runtime};
+
+// enough for most short programs to finish, while keeping each run fast
+const BUDGET: u64 = 100_000;
+
+fuzz_target!(|prog: &str| {
+    // builtins that print or sleep do nothing, so that runs are quiet and fast
+    let mut builtins = runtime::builtins();
+    for builtin in builtins.iter_mut() {
+        if ["out", "load", "wait"].contains(&&builtin.name[..]) {
+            *builtin = NativeFn::new(&builtin.name.clone(), |_ctx, _args| Ok(Val::Null));
+        }
+    }
+
+    if let Ok(blocks) = debug::compile(prog, builtins) {
+        let mut vm = Vm::new(blocks);
+        vm.set_budget(BUDGET);
+        let _ = vm.run();
+    }
+});
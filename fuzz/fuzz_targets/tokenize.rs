@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use schrift::lex;
+
+fuzz_target!(|prog: &str| {
+    let _ = lex::tokenize(prog);
+});
@@ -1,90 +1,488 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
 use crate::err::InkErr;
-use crate::lex::TokKind;
-use crate::parse::Node;
+use crate::lex::{Span, TokKind};
+use crate::parse::{Node, Resolved};
+use crate::runtime;
+use crate::val::Val;
+use crate::visit::{walk_mut, VisitorMut};
 
-pub fn analyze(nodes: &mut Vec<Node>) -> Result<(), InkErr> {
+// analyze checks and simplifies a program, then resolves the names in it.
+// predefined names are the ones the program is compiled with, like builtins.
+pub fn analyze(nodes: &mut Vec<Node>, predefined: &[String]) -> Result<Resolution, InkErr> {
+    let mut analyzer = Analyzer {};
     for node in nodes.iter_mut() {
-        analyze_node(node)?;
+        analyzer.visit_mut(node)?;
     }
-    return Ok(());
+    return resolve(nodes, predefined);
 }
 
-fn analyze_node(node: &mut Node) -> Result<(), InkErr> {
-    match node {
-        Node::UnaryExpr { op: _, arg } => {
-            analyze_node(arg)?;
-        }
-        Node::BinaryExpr {
-            op: TokKind::DefineOp,
-            left,
-            right,
-        } => {
-            analyze_node(right)?;
-            match *(left.clone()) {
-                Node::Ident(_) => (),
-                Node::BinaryExpr {
-                    op: TokKind::AccessorOp,
-                    left: mut comp_left,
-                    right: mut comp_right,
-                } => {
-                    analyze_node(&mut comp_left)?;
-                    analyze_node(&mut comp_right)?;
+// Analyzer checks each node and simplifies it, after its children
+struct Analyzer {}
+
+impl VisitorMut for Analyzer {
+    fn visit_mut(&mut self, node: &mut Node) -> Result<(), InkErr> {
+        match node {
+            Node::BinaryExpr {
+                op: TokKind::DefineOp,
+                left,
+                right,
+            } => {
+                match **left {
+                    Node::Ident(_, _, _)
+                    | Node::BinaryExpr {
+                        op: TokKind::AccessorOp,
+                        left: _,
+                        right: _,
+                    } => (),
+                    _ => return Err(InkErr::InvalidAssignment),
                 }
-                _ => return Err(InkErr::InvalidAssignment),
+                self.visit_mut(left)?;
+                self.visit_mut(right)?;
+            }
+            Node::BinaryExpr {
+                op: TokKind::AccessorOp,
+                left,
+                right,
+            } => {
+                self.visit_mut(left)?;
+                self.visit_property(right)?;
+            }
+            Node::ObjectEntry { key, val } => {
+                self.visit_property(key)?;
+                self.visit_mut(val)?;
             }
+            _ => walk_mut(self, node)?,
         }
-        Node::BinaryExpr { op: _, left, right } => {
-            analyze_node(left)?;
-            analyze_node(right)?;
+
+        simplify(node);
+        return Ok(());
+    }
+}
+
+impl Analyzer {
+    // visit_property analyzes a property name, like `b` in `a.b` or `{b: c}`.
+    // A group there is never unwrapped, because `a.(b)` looks up the value of
+    // `b` but `a.b` looks up the name "b".
+    fn visit_property(&mut self, node: &mut Node) -> Result<(), InkErr> {
+        return match node {
+            Node::ExprList(_) => walk_mut(self, node),
+            _ => self.visit_mut(node),
+        };
+    }
+}
+
+// simplify replaces a node whose children are already analyzed with a simpler
+// one that evaluates to the same value, if there is one. Arithmetic on number
+// literals and concatenation of string literals are folded into a literal, and
+// a group of one expression is unwrapped, because each group is compiled into
+// a block of its own and a call to it.
+fn simplify(node: &mut Node) {
+    let simpler = match node {
+        Node::UnaryExpr { op: _, arg } => match **arg {
+            Node::NumberLiteral(num, span) => Some(Node::NumberLiteral(-num, span)),
+            _ => None,
+        },
+        Node::BinaryExpr { op, left, right } => fold(op, left, right),
+        // a group is its own scope, so it's kept if it defines any names, and
+        // `(_)` is a value where `_` alone would match anything
+        Node::ExprList(exprs)
+            if exprs.len() == 1
+                && !matches!(exprs[0], Node::EmptyIdent)
+                && !defines_names(&exprs[0]) =>
+        {
+            Some(exprs.remove(0))
         }
-        Node::FnCall { func, args } => {
-            analyze_node(func)?;
-            for arg in args.iter_mut() {
-                analyze_node(arg)?;
+        _ => None,
+    };
+
+    if let Some(simpler) = simpler {
+        *node = simpler;
+    }
+}
+
+// fold returns the literal that a binary operation on two literals evaluates
+// to, computed the way the VM would. Operations that would fail at runtime,
+// or give a number that can't be written as a literal, are left to the VM.
+fn fold(op: &TokKind, left: &Node, right: &Node) -> Option<Node> {
+    return match (left, right) {
+        (Node::NumberLiteral(a, a_span), Node::NumberLiteral(b, b_span)) => {
+            let (a, b) = (Val::Number(*a), Val::Number(*b));
+            let result = match op {
+                TokKind::AddOp => runtime::add(&a, &b),
+                TokKind::SubOp => runtime::sub(&a, &b),
+                TokKind::MulOp => runtime::mul(&a, &b),
+                TokKind::DivOp => runtime::div(&a, &b),
+                TokKind::ModOp => runtime::modulus(&a, &b),
+                _ => return None,
+            };
+            match result {
+                Ok(Val::Number(num)) if num.is_finite() => {
+                    Some(Node::NumberLiteral(num, Span(a_span.0, b_span.1)))
+                }
+                _ => None,
             }
         }
-        Node::MatchClause { target, expr } => {
-            analyze_node(target)?;
-            analyze_node(expr)?;
+        // string literals hold complete escape sequences, so they can be
+        // joined as written
+        (Node::StringLiteral(a, a_span), Node::StringLiteral(b, b_span))
+            if *op == TokKind::AddOp =>
+        {
+            Some(Node::StringLiteral(
+                format!("{}{}", a, b),
+                Span(a_span.0, b_span.1),
+            ))
         }
+        _ => None,
+    };
+}
+
+// defines_names reports whether evaluating a node defines a name in the scope
+// it's evaluated in. Functions, groups and match clauses' expressions have
+// scopes of their own.
+fn defines_names(node: &Node) -> bool {
+    return match node {
+        Node::BinaryExpr {
+            op: TokKind::DefineOp,
+            left,
+            right,
+        } => match **left {
+            Node::Ident(_, _, _) => true,
+            _ => defines_names(left) || defines_names(right),
+        },
+        Node::UnaryExpr { op: _, arg } => defines_names(arg),
+        Node::BinaryExpr { op: _, left, right } => defines_names(left) || defines_names(right),
+        Node::FnCall { func, args } => defines_names(func) || args.iter().any(defines_names),
+        Node::MatchClause { target, expr: _ } => defines_names(target),
         Node::MatchExpr { cond, clauses } => {
-            analyze_node(cond)?;
-            for clause in clauses.iter_mut() {
-                analyze_node(clause)?;
+            defines_names(cond) || clauses.iter().any(defines_names)
+        }
+        Node::ObjectLiteral(nodes) | Node::ListLiteral(nodes) => nodes.iter().any(defines_names),
+        Node::ObjectEntry { key, val } => defines_names(key) || defines_names(val),
+        Node::Trivia { node, .. } => defines_names(node),
+        Node::ExprList(_)
+        | Node::FnLiteral { args: _, body: _ }
+        | Node::EmptyIdent
+        | Node::Ident(_, _, _)
+        | Node::NumberLiteral(_, _)
+        | Node::StringLiteral(_, _)
+        | Node::BooleanLiteral(_, _) => false,
+    };
+}
+
+// BindingKind is what the analyzer knows about the value of a name from where
+// it is defined
+#[derive(Debug, Clone, PartialEq)]
+pub enum BindingKind {
+    Builtin,
+    Argument,
+    Function(Vec<String>),
+    Number,
+    String,
+    Boolean,
+    Composite,
+    Value,
+}
+
+impl BindingKind {
+    fn of(node: &Node) -> BindingKind {
+        return match node {
+            Node::FnLiteral { args, body: _ } => BindingKind::Function(
+                args.iter()
+                    .map(|arg| match arg {
+                        Node::Ident(name, _, _) => name.clone(),
+                        _ => "_".to_string(),
+                    })
+                    .collect(),
+            ),
+            Node::NumberLiteral(_, _) => BindingKind::Number,
+            Node::StringLiteral(_, _) => BindingKind::String,
+            Node::BooleanLiteral(_, _) => BindingKind::Boolean,
+            Node::ObjectLiteral(_) | Node::ListLiteral(_) => BindingKind::Composite,
+            _ => BindingKind::Value,
+        };
+    }
+}
+
+impl fmt::Display for BindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindingKind::Builtin => write!(f, "builtin function"),
+            BindingKind::Argument => write!(f, "argument"),
+            BindingKind::Function(args) => write!(f, "function ({})", args.join(", ")),
+            BindingKind::Number => write!(f, "number"),
+            BindingKind::String => write!(f, "string"),
+            BindingKind::Boolean => write!(f, "boolean"),
+            BindingKind::Composite => write!(f, "composite"),
+            BindingKind::Value => write!(f, "value"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub name: String,
+    // span is the name where it is first defined, or None for predefined names
+    pub span: Option<Span>,
+    pub kind: BindingKind,
+    // depth is the number of scopes around the binding, 0 for top-level names
+    pub depth: usize,
+}
+
+// Resolution records which binding every name in a program refers to, for
+// editor tooling and the checker.
+#[derive(Debug, Clone, Default)]
+pub struct Resolution {
+    pub bindings: Vec<Binding>,
+    // references are spans of names with the index of their binding, including
+    // later assignments to an already defined name
+    pub references: Vec<(Span, usize)>,
+    pub undefined: Vec<(String, Span)>,
+}
+
+// resolve annotates each name in a program with the binding it refers to, by
+// the compiler's scope rules, and returns every binding with the names that
+// refer to it. The program, each function, each group of expressions and the
+// expression of each match clause are scopes. A name defined with := as an
+// item of a scope is declared from the start of the scope, so functions can
+// refer to names defined after them, and a name defined inside another
+// expression, like `f(x := 1)`, from where it's defined. := always defines a
+// name in the scope it's in, and the scope's own code reads the name from the
+// scopes around it until then. Names no scope defines are predefined names if
+// the program is compiled with them, and undefined otherwise.
+//
+// Once every name is resolved, definitions of names that a scope inside their
+// own captures are marked as escaping, so the code generator can move them to
+// the heap where they're defined.
+pub fn resolve(nodes: &mut [Node], predefined: &[String]) -> Result<Resolution, InkErr> {
+    let mut resolver = Resolver {
+        scopes: vec![HashMap::new()],
+        predefined: HashMap::new(),
+        escapes: vec![],
+        locals: vec![],
+        resolution: Resolution::default(),
+    };
+    for name in predefined {
+        if !resolver.predefined.contains_key(name) {
+            let binding = resolver.bind(name, None, BindingKind::Builtin);
+            resolver.predefined.insert(name.clone(), binding);
+        }
+    }
+    resolver.scope(nodes)?;
+
+    let escaping = resolver
+        .locals
+        .iter()
+        .filter(|(_, binding)| resolver.escapes[*binding])
+        .map(|(start, _)| *start)
+        .collect();
+    let mut marker = EscapeMarker { escaping };
+    for node in nodes.iter_mut() {
+        marker.visit_mut(node)?;
+    }
+    return Ok(resolver.resolution);
+}
+
+struct Resolver {
+    // the names declared in each scope, innermost last, with their bindings
+    // and whether the scope's own code has defined them yet
+    scopes: Vec<HashMap<String, (usize, bool)>>,
+    // bindings of the names the program is compiled with
+    predefined: HashMap<String, usize>,
+    // whether each binding is captured by a scope inside the one it's in
+    escapes: Vec<bool>,
+    // where each name resolved to a local binding starts, with the binding
+    locals: Vec<(usize, usize)>,
+    resolution: Resolution,
+}
+
+impl VisitorMut for Resolver {
+    fn visit_mut(&mut self, node: &mut Node) -> Result<(), InkErr> {
+        match node {
+            Node::BinaryExpr {
+                op: TokKind::DefineOp,
+                left,
+                right,
+            } => {
+                self.visit_mut(right)?;
+                match &mut **left {
+                    Node::Ident(name, span, resolved) => {
+                        let binding = self.declare(name, *span, BindingKind::of(right), true);
+                        self.refer(binding, *span);
+                        self.locals.push((span.0, binding));
+                        *resolved = Resolved::Local { escapes: false };
+                    }
+                    Node::BinaryExpr {
+                        op: TokKind::AccessorOp,
+                        left,
+                        right,
+                    } => {
+                        self.visit_mut(left)?;
+                        self.visit_property(right)?;
+                    }
+                    // anything else is an invalid assignment, which is
+                    // reported, but the names in it are still used
+                    _ => self.visit_mut(left)?,
+                }
             }
+            Node::BinaryExpr {
+                op: TokKind::AccessorOp,
+                left,
+                right,
+            } => {
+                self.visit_mut(left)?;
+                self.visit_property(right)?;
+            }
+            Node::ObjectEntry { key, val } => {
+                self.visit_property(key)?;
+                self.visit_mut(val)?;
+            }
+            Node::Ident(name, span, resolved) => *resolved = self.lookup(name, *span),
+            Node::ExprList(exprs) if !exprs.is_empty() => {
+                self.scopes.push(HashMap::new());
+                self.scope(exprs)?;
+                self.scopes.pop();
+            }
+            Node::FnLiteral { args, body } => {
+                self.scopes.push(HashMap::new());
+                for arg in args.iter_mut() {
+                    if let Node::Ident(name, span, resolved) = arg {
+                        let binding = self.declare(name, *span, BindingKind::Argument, true);
+                        self.locals.push((span.0, binding));
+                        *resolved = Resolved::Local { escapes: false };
+                    }
+                }
+                self.body(body)?;
+                self.scopes.pop();
+            }
+            // each clause's expression is compiled as a function of no arguments
+            Node::MatchClause { target, expr } => {
+                self.visit_mut(target)?;
+                self.scopes.push(HashMap::new());
+                self.body(expr)?;
+                self.scopes.pop();
+            }
+            _ => walk_mut(self, node)?,
         }
-        Node::ExprList(exprs) => {
-            for expr in exprs.iter_mut() {
-                analyze_node(expr)?;
+        return Ok(());
+    }
+}
+
+impl Resolver {
+    // scope resolves the items of a scope, after declaring the names they
+    // define
+    fn scope(&mut self, items: &mut [Node]) -> Result<(), InkErr> {
+        for item in items.iter() {
+            if let Node::BinaryExpr {
+                op: TokKind::DefineOp,
+                left,
+                right,
+            } = item
+            {
+                if let Node::Ident(name, span, _) = &**left {
+                    self.declare(name, *span, BindingKind::of(right), false);
+                }
             }
         }
+        for item in items.iter_mut() {
+            self.visit_mut(item)?;
+        }
+        return Ok(());
+    }
+
+    // body resolves the body of a function, whose items are in the scope of
+    // the function itself
+    fn body(&mut self, body: &mut Node) -> Result<(), InkErr> {
+        return match body {
+            Node::ExprList(exprs) => self.scope(exprs),
+            _ => self.scope(std::slice::from_mut(body)),
+        };
+    }
 
-        Node::EmptyIdent => (),
-        Node::Ident(_) => (),
-        Node::NumberLiteral(_) => (),
-        Node::StringLiteral(_) => (),
-        Node::BooleanLiteral(_) => (),
+    // visit_property resolves a property name, like `b` in `a.b` or `{b: c}`,
+    // which is only a name to look up when it's not an identifier
+    fn visit_property(&mut self, node: &mut Node) -> Result<(), InkErr> {
+        return match node {
+            Node::Ident(_, _, _) => Ok(()),
+            _ => self.visit_mut(node),
+        };
+    }
 
-        Node::ObjectLiteral(entries) => {
-            for entry in entries.iter_mut() {
-                analyze_node(entry)?;
-            }
+    // declare returns the binding of a name in the innermost scope, which is
+    // new unless the scope already declares the name. A name defined more
+    // than once in a scope keeps its first definition.
+    fn declare(&mut self, name: &str, span: Span, kind: BindingKind, defined: bool) -> usize {
+        let scope = self.scopes.last_mut().unwrap();
+        if let Some((existing, existing_defined)) = scope.get_mut(name) {
+            *existing_defined |= defined;
+            return *existing;
         }
-        Node::ObjectEntry { key, val } => {
-            analyze_node(key)?;
-            analyze_node(val)?;
+        let binding = self.escapes.len();
+        scope.insert(name.to_string(), (binding, defined));
+        return self.bind(name, Some(span), kind);
+    }
+
+    // bind adds a binding in the innermost scope
+    fn bind(&mut self, name: &str, span: Option<Span>, kind: BindingKind) -> usize {
+        self.resolution.bindings.push(Binding {
+            name: name.to_string(),
+            span,
+            kind,
+            depth: self.scopes.len() - 1,
+        });
+        self.escapes.push(false);
+        return self.escapes.len() - 1;
+    }
+
+    // refer records a use of a binding, unless it's where the binding is
+    // first defined
+    fn refer(&mut self, binding: usize, span: Span) {
+        if self.resolution.bindings[binding].span != Some(span) {
+            self.resolution.references.push((span, binding));
         }
-        Node::ListLiteral(items) => {
-            for item in items.iter_mut() {
-                analyze_node(item)?;
+    }
+
+    // lookup resolves a name where it's read. Code that runs as part of a
+    // scope, rather than in a function or group inside it, reads a name from
+    // the scopes around it until the scope defines it, so `x := x + 1` in a
+    // function reads the `x` around the function.
+    fn lookup(&mut self, name: &str, span: Span) -> Resolved {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some((binding, defined)) = scope.get(name) {
+                if depth == 0 && !defined {
+                    continue;
+                }
+                let binding = *binding;
+                self.refer(binding, span);
+                if depth == 0 {
+                    self.locals.push((span.0, binding));
+                    return Resolved::Local { escapes: false };
+                }
+                self.escapes[binding] = true;
+                return Resolved::Captured(depth);
             }
         }
-        Node::FnLiteral { args, body } => {
-            for arg in args.iter_mut() {
-                analyze_node(arg)?;
-            }
-            analyze_node(body)?;
+        if let Some(binding) = self.predefined.get(name).copied() {
+            self.refer(binding, span);
+            return Resolved::Builtin;
+        }
+        self.resolution.undefined.push((name.to_string(), span));
+        return Resolved::Undefined;
+    }
+}
+
+// EscapeMarker marks names that refer to escaping local bindings, given where
+// they start
+struct EscapeMarker {
+    escaping: HashSet<usize>,
+}
+
+impl VisitorMut for EscapeMarker {
+    fn visit_mut(&mut self, node: &mut Node) -> Result<(), InkErr> {
+        if let Node::Ident(_, span, Resolved::Local { escapes }) = node {
+            *escapes = self.escaping.contains(&span.0);
         }
+        return walk_mut(self, node);
     }
-    return Ok(());
 }
@@ -17,6 +17,14 @@ pub enum EvalMode {
 #[derive(Clone)]
 pub enum Action {
     Eval(EvalMode),
+    Compile(PathBuf),
+    RunAsm(PathBuf),
+    Debug(PathBuf),
+    Check(PathBuf),
+    Fmt(Vec<PathBuf>),
+    Test(PathBuf),
+    Dap,
+    Lsp,
     Version,
     Help,
 }
@@ -30,6 +38,25 @@ pub struct Opts {
     pub debug_analyze: bool,
     pub debug_compile: bool,
     pub debug_optimize: bool,
+
+    pub output: Option<PathBuf>,
+
+    pub profile: bool,
+    pub profile_output: Option<PathBuf>,
+
+    pub trace: bool,
+    pub trace_json: bool,
+    pub trace_output: Option<PathBuf>,
+    pub trace_blocks: Vec<usize>,
+    pub trace_fns: Vec<String>,
+
+    pub coverage: Option<PathBuf>,
+
+    pub update: bool,
+
+    pub json: bool,
+
+    pub check: bool,
 }
 
 pub fn get_cli_opts() -> Opts {
@@ -44,31 +71,80 @@ pub fn get_cli_opts() -> Opts {
         debug_analyze: false,
         debug_compile: false,
         debug_optimize: false,
+
+        output: None,
+
+        profile: false,
+        profile_output: None,
+
+        trace: false,
+        trace_json: false,
+        trace_output: None,
+        trace_blocks: vec![],
+        trace_fns: vec![],
+
+        coverage: None,
+
+        update: false,
+
+        json: false,
+
+        check: false,
     };
 
-    opts.action = if args.len() == 0 {
-        Action::Eval(EvalMode::Repl)
-    } else {
-        match &(args[0][..]) {
-            "version" => Action::Version,
-            "help" => Action::Help,
-            "eval" => {
-                if args.len() >= 2 {
-                    let prog = String::from(args[1].clone());
-                    Action::Eval(EvalMode::Eval(prog))
-                } else {
-                    Action::Help
+    // positional arguments are all arguments that are not flags or flag values
+    let mut positional = Vec::<String>::new();
+    let mut arg_iter = args.iter();
+    while let Some(arg) = arg_iter.next() {
+        match &arg[..] {
+            "-o" | "--output" => {
+                if let Some(path_str) = arg_iter.next() {
+                    opts.output = Some(PathBuf::from(path_str));
                 }
+                continue;
             }
-            path_str => {
-                let mut path = PathBuf::new();
-                path.push(path_str);
-                Action::Eval(EvalMode::RunFile(path))
+            "--profile-output" => {
+                if let Some(path_str) = arg_iter.next() {
+                    opts.profile = true;
+                    opts.profile_output = Some(PathBuf::from(path_str));
+                }
+                continue;
+            }
+            // trace filters and outputs imply --trace
+            "--trace-output" => {
+                if let Some(path_str) = arg_iter.next() {
+                    opts.trace = true;
+                    opts.trace_output = Some(PathBuf::from(path_str));
+                }
+                continue;
             }
+            "--trace-block" => {
+                if let Some(block_idx) = arg_iter.next().and_then(|s| s.parse().ok()) {
+                    opts.trace = true;
+                    opts.trace_blocks.push(block_idx);
+                }
+                continue;
+            }
+            "--trace-fn" => {
+                if let Some(name) = arg_iter.next() {
+                    opts.trace = true;
+                    opts.trace_fns.push(name.clone());
+                }
+                continue;
+            }
+            "--coverage" => {
+                if let Some(path_str) = arg_iter.next() {
+                    opts.coverage = Some(PathBuf::from(path_str));
+                }
+                continue;
+            }
+            _ if !arg.starts_with("-") => {
+                positional.push(arg.clone());
+                continue;
+            }
+            _ => (),
         }
-    };
 
-    for arg in args.iter() {
         if arg.starts_with("--") {
             let flag_str = &arg[2..];
             match flag_str {
@@ -77,6 +153,15 @@ pub fn get_cli_opts() -> Opts {
                 "debug-analyze" => opts.debug_analyze = true,
                 "debug-compile" => opts.debug_compile = true,
                 "debug-optimize" => opts.debug_optimize = true,
+                "profile" => opts.profile = true,
+                "update" => opts.update = true,
+                "json" => opts.json = true,
+                "check" => opts.check = true,
+                "trace" => opts.trace = true,
+                "trace-json" => {
+                    opts.trace = true;
+                    opts.trace_json = true;
+                }
                 _ => (),
             }
         }
@@ -94,5 +179,68 @@ pub fn get_cli_opts() -> Opts {
         }
     }
 
+    opts.action = if positional.len() == 0 {
+        Action::Eval(EvalMode::Repl)
+    } else {
+        match &(positional[0][..]) {
+            "version" => Action::Version,
+            "help" => Action::Help,
+            "eval" => {
+                if positional.len() >= 2 {
+                    let prog = String::from(positional[1].clone());
+                    Action::Eval(EvalMode::Eval(prog))
+                } else {
+                    Action::Help
+                }
+            }
+            "compile" => {
+                if positional.len() >= 2 {
+                    Action::Compile(PathBuf::from(&positional[1]))
+                } else {
+                    Action::Help
+                }
+            }
+            "debug" => {
+                if positional.len() >= 2 {
+                    Action::Debug(PathBuf::from(&positional[1]))
+                } else {
+                    Action::Help
+                }
+            }
+            "check" => {
+                if positional.len() >= 2 {
+                    Action::Check(PathBuf::from(&positional[1]))
+                } else {
+                    Action::Help
+                }
+            }
+            "fmt" => {
+                if positional.len() >= 2 {
+                    Action::Fmt(positional[1..].iter().map(PathBuf::from).collect())
+                } else {
+                    Action::Help
+                }
+            }
+            "test" => match positional.get(1) {
+                Some(path_str) => Action::Test(PathBuf::from(path_str)),
+                None => Action::Test(PathBuf::from("test")),
+            },
+            "dap" => Action::Dap,
+            "lsp" => Action::Lsp,
+            "run-asm" => {
+                if positional.len() >= 2 {
+                    Action::RunAsm(PathBuf::from(&positional[1]))
+                } else {
+                    Action::Help
+                }
+            }
+            path_str => {
+                let mut path = PathBuf::new();
+                path.push(path_str);
+                Action::Eval(EvalMode::RunFile(path))
+            }
+        }
+    };
+
     return opts;
 }
@@ -0,0 +1,376 @@
+// Textual bytecode assembler. assemble reads the block listing format printed
+// by --debug-compile (and by Block's Display impl) back into a program, so
+// that VM regression tests and optimizer fixtures can be written directly in
+// bytecode. A listing looks like
+//
+//     #0
+//     name: greet
+//     slots: 3
+//     consts: [NativeFunc(out), 'hi']
+//     binds: []
+//       @0	LOAD_CONST 0
+//       @1	LOAD_CONST 1
+//       @2	CALL @0, [@1]
+//
+// The name and slots lines are optional, and slots are inferred from the
+// registers used in the block when missing. Lines starting with `;` are
+// comments.
+
+use crate::err::InkErr;
+use crate::gen::{Block, Inst, Op, Reg};
+use crate::val::{NativeFn, Val};
+
+pub fn disassemble(prog: &[Block]) -> String {
+    let mut listing = String::new();
+    for (i, block) in prog.iter().enumerate() {
+        listing.push_str(&format!("#{}\n{}", i, block));
+    }
+    return listing;
+}
+
+pub fn assemble(source: &str, builtins: &[NativeFn]) -> Result<Vec<Block>, InkErr> {
+    let mut prog = Vec::<Block>::new();
+    let mut explicit_slots = Vec::<bool>::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with("::") {
+            continue;
+        }
+
+        let mut cursor = Cursor {
+            line: line_no,
+            text: line,
+            idx: 0,
+            builtins,
+        };
+
+        if cursor.eat("#") {
+            let block_idx = cursor.num()?;
+            if block_idx != prog.len() {
+                return Err(cursor.error(&format!(
+                    "expected block #{}, found #{}",
+                    prog.len(),
+                    block_idx
+                )));
+            }
+            prog.push(Block::new());
+            explicit_slots.push(false);
+            cursor.end()?;
+            continue;
+        }
+
+        let block = match prog.last_mut() {
+            Some(block) => block,
+            None => return Err(cursor.error("expected a block header like #0")),
+        };
+
+        if cursor.eat("name:") {
+            block.name = cursor.take_while(|c| !c.is_whitespace()).to_string();
+        } else if cursor.eat("slots:") {
+            block.slots = cursor.num()?;
+            *explicit_slots.last_mut().unwrap() = true;
+        } else if cursor.eat("consts:") {
+            cursor.expect("[")?;
+            while !cursor.eat("]") {
+                if !block.consts.is_empty() {
+                    cursor.expect(",")?;
+                }
+                block.consts.push(cursor.val()?);
+            }
+        } else if cursor.eat("binds:") {
+            cursor.expect("[")?;
+            while !cursor.eat("]") {
+                if !block.binds.is_empty() {
+                    cursor.expect(",")?;
+                }
+                block.binds.push(cursor.num()?);
+            }
+        } else {
+            block.code.push(cursor.inst()?);
+        }
+        cursor.end()?;
+    }
+
+    // infer register counts for blocks without an explicit slots line
+    for block_idx in 0..prog.len() {
+        if !explicit_slots[block_idx] {
+            prog[block_idx].slots = inferred_slots(&prog, &prog[block_idx]);
+        }
+    }
+
+    return Ok(prog);
+}
+
+fn inferred_slots(prog: &[Block], block: &Block) -> usize {
+    let mut regs = Vec::<Reg>::new();
+    for inst in block.code.iter() {
+        regs.push(inst.dest);
+        match &inst.op {
+            Op::Nop | Op::MakeComp | Op::LoadEsc(_) => (),
+            Op::LoadConst(idx) => {
+                // loading a closure reads its binds from this block's registers
+                if let Some(Val::Func(callee_idx, _)) = block.consts.get(*idx) {
+                    if let Some(callee) = prog.get(*callee_idx) {
+                        regs.extend(callee.binds.iter());
+                    }
+                }
+            }
+            Op::Mov(reg) | Op::Escape(reg) | Op::Neg(reg) => regs.push(*reg),
+            Op::Call(reg, args) => {
+                regs.push(*reg);
+                regs.extend(args.iter());
+            }
+            Op::CallIfEq(reg, a, b, _) | Op::SetComp(reg, a, b) => regs.extend(&[*reg, *a, *b]),
+            Op::GetComp(a, b)
+            | Op::Add(a, b)
+            | Op::Sub(a, b)
+            | Op::Mul(a, b)
+            | Op::Div(a, b)
+            | Op::Mod(a, b)
+            | Op::Gtr(a, b)
+            | Op::Lss(a, b)
+            | Op::Eql(a, b)
+            | Op::And(a, b)
+            | Op::Or(a, b)
+            | Op::Xor(a, b) => regs.extend(&[*a, *b]),
+        }
+    }
+    return match regs.iter().max() {
+        Some(max) => max + 1,
+        None => 0,
+    };
+}
+
+struct Cursor<'s> {
+    line: usize,
+    text: &'s str,
+    idx: usize,
+    builtins: &'s [NativeFn],
+}
+
+impl<'s> Cursor<'s> {
+    fn error(&self, msg: &str) -> InkErr {
+        return InkErr::AssemblyError(format!("line {}: {}", self.line, msg));
+    }
+
+    fn rest(&self) -> &'s str {
+        return &self.text[self.idx..];
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.idx += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(s) {
+            self.idx += s.len();
+            return true;
+        }
+        return false;
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), InkErr> {
+        if self.eat(s) {
+            return Ok(());
+        }
+        return Err(self.error(&format!("expected \"{}\" at \"{}\"", s, self.rest())));
+    }
+
+    fn end(&mut self) -> Result<(), InkErr> {
+        self.skip_whitespace();
+        if self.rest().is_empty() || self.rest().starts_with(';') {
+            return Ok(());
+        }
+        return Err(self.error(&format!("unexpected \"{}\"", self.rest())));
+    }
+
+    fn take_while<F>(&mut self, cond: F) -> &'s str
+    where
+        F: Fn(char) -> bool,
+    {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest.find(|c| !cond(c)).unwrap_or(rest.len());
+        self.idx += len;
+        return &rest[..len];
+    }
+
+    fn num(&mut self) -> Result<usize, InkErr> {
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        return match digits.parse::<usize>() {
+            Ok(n) => Ok(n),
+            Err(_) => Err(self.error(&format!("expected a number at \"{}\"", self.rest()))),
+        };
+    }
+
+    fn reg(&mut self) -> Result<Reg, InkErr> {
+        self.expect("@")?;
+        return self.num();
+    }
+
+    fn val(&mut self) -> Result<Val, InkErr> {
+        if self.eat("'") {
+            return self.str_val();
+        }
+        if self.eat("Func(") {
+            let block_idx = self.num()?;
+            self.expect(",")?;
+            self.expect("[")?;
+            let mut heap_vals = Vec::<Val>::new();
+            while !self.eat("]") {
+                if !heap_vals.is_empty() {
+                    self.expect(",")?;
+                }
+                heap_vals.push(self.val()?);
+            }
+            self.expect(")")?;
+            return Ok(Val::Func(block_idx, heap_vals));
+        }
+        if self.eat("NativeFunc(") {
+            let name = self.take_while(|c| c != ')');
+            self.expect(")")?;
+            return match self.builtins.iter().find(|builtin| builtin.name == name) {
+                Some(builtin) => Ok(Val::NativeFunc(builtin.clone())),
+                None => Err(InkErr::UnknownBuiltin(name.to_string())),
+            };
+        }
+        if self.eat("()") {
+            return Ok(Val::Null);
+        }
+        if self.eat("true") {
+            return Ok(Val::Bool(true));
+        }
+        if self.eat("false") {
+            return Ok(Val::Bool(false));
+        }
+        if self.eat("_") {
+            return Ok(Val::Empty);
+        }
+
+        let numeral = self.take_while(|c| c != ',' && c != ']' && !c.is_whitespace());
+        return match numeral.parse::<f64>() {
+            Ok(n) => Ok(Val::Number(n)),
+            Err(_) => Err(self.error(&format!("invalid constant \"{}\"", numeral))),
+        };
+    }
+
+    // str_val reads the rest of a quoted string constant, after the opening quote
+    fn str_val(&mut self) -> Result<Val, InkErr> {
+        let mut s = Vec::<u8>::new();
+        let bytes = self.text.as_bytes();
+        loop {
+            let b = match bytes.get(self.idx) {
+                Some(b) => *b,
+                None => return Err(self.error("unterminated string constant")),
+            };
+            self.idx += 1;
+
+            match b {
+                b'\'' => return Ok(Val::Str(s)),
+                b'\\' => {
+                    let escaped = match bytes.get(self.idx) {
+                        Some(b) => *b,
+                        None => return Err(self.error("unterminated string constant")),
+                    };
+                    self.idx += 1;
+                    match escaped {
+                        b'n' => s.push(b'\n'),
+                        b't' => s.push(b'\t'),
+                        b'x' => {
+                            let hex = self.text.get(self.idx..self.idx + 2).unwrap_or("");
+                            match u8::from_str_radix(hex, 16) {
+                                Ok(b) => s.push(b),
+                                Err(_) => return Err(self.error("invalid \\x escape")),
+                            }
+                            self.idx += 2;
+                        }
+                        _ => s.push(escaped),
+                    }
+                }
+                _ => s.push(b),
+            }
+        }
+    }
+
+    fn inst(&mut self) -> Result<Inst, InkErr> {
+        let dest = self.reg()?;
+
+        // binary and unary operators are written infix
+        if self.eat("~") {
+            return Ok(Inst {
+                dest,
+                op: Op::Neg(self.reg()?),
+            });
+        }
+        self.skip_whitespace();
+        if self.rest().starts_with('@') {
+            let a = self.reg()?;
+            let op_str = self.take_while(|c| !c.is_whitespace());
+            let b = self.reg()?;
+            let op = match op_str {
+                "+" => Op::Add(a, b),
+                "-" => Op::Sub(a, b),
+                "*" => Op::Mul(a, b),
+                "/" => Op::Div(a, b),
+                "%" => Op::Mod(a, b),
+                ">" => Op::Gtr(a, b),
+                "<" => Op::Lss(a, b),
+                "=" => Op::Eql(a, b),
+                "&" => Op::And(a, b),
+                "|" => Op::Or(a, b),
+                "^" => Op::Xor(a, b),
+                _ => return Err(self.error(&format!("unknown operator \"{}\"", op_str))),
+            };
+            return Ok(Inst { dest, op });
+        }
+
+        let mnemonic = self.take_while(|c| c.is_ascii_uppercase() || c == '_');
+        let op = match mnemonic {
+            "NOP" => Op::Nop,
+            "MOV" => Op::Mov(self.reg()?),
+            "ESCAPE" => Op::Escape(self.reg()?),
+            "LOAD_CONST" => Op::LoadConst(self.num()?),
+            "LOAD_ESC" => Op::LoadEsc(self.num()?),
+            "CALL" => {
+                let reg = self.reg()?;
+                self.expect(",")?;
+                self.expect("[")?;
+                let mut args = Vec::<Reg>::new();
+                while !self.eat("]") {
+                    if !args.is_empty() {
+                        self.expect(",")?;
+                    }
+                    args.push(self.reg()?);
+                }
+                Op::Call(reg, args)
+            }
+            "CALL_IF_EQ" => {
+                let reg = self.reg()?;
+                self.expect(",")?;
+                let a = self.reg()?;
+                self.expect("==")?;
+                let b = self.reg()?;
+                self.expect(",")?;
+                Op::CallIfEq(reg, a, b, self.num()?)
+            }
+            "MAKE_COMP" => Op::MakeComp,
+            "SET_COMP" => {
+                let reg = self.reg()?;
+                self.expect(",")?;
+                let k = self.reg()?;
+                Op::SetComp(reg, k, self.reg()?)
+            }
+            "GET_COMP" => {
+                let reg = self.reg()?;
+                self.expect(",")?;
+                Op::GetComp(reg, self.reg()?)
+            }
+            _ => return Err(self.error(&format!("unknown instruction \"{}\"", mnemonic))),
+        };
+        return Ok(Inst { dest, op });
+    }
+}
@@ -0,0 +1,391 @@
+// Binary serialization of compiled programs (.inkb files), so that programs
+// can skip lexing, parsing, analysis, code generation and optimization.
+//
+// An .inkb file is the magic bytes "INKB", a little-endian u32 format
+// version, and the program's blocks in order. All integers are little-endian
+// u32's, and strings are a u32 length followed by raw bytes. Native function
+// constants are stored by name and resolved against the runtime's builtins
+// when the program is loaded.
+
+use crate::err::InkErr;
+use crate::gen::{Block, Inst, Op, Reg};
+use crate::val::{NativeFn, Val};
+
+const MAGIC: &[u8] = b"INKB";
+
+// FORMAT_VERSION must be incremented whenever the encoding of any part of the
+// program changes, including additions to Op.
+pub const FORMAT_VERSION: u32 = 2;
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    return bytes.starts_with(MAGIC);
+}
+
+pub fn encode(prog: &[Block]) -> Result<Vec<u8>, InkErr> {
+    let mut w = Writer { buf: vec![] };
+    w.buf.extend_from_slice(MAGIC);
+    w.u32(FORMAT_VERSION);
+
+    w.len(prog.len());
+    for block in prog.iter() {
+        w.block(block)?;
+    }
+
+    return Ok(w.buf);
+}
+
+pub fn decode(bytes: &[u8], builtins: &[NativeFn]) -> Result<Vec<Block>, InkErr> {
+    let mut r = Reader {
+        bytes,
+        idx: 0,
+        builtins,
+    };
+
+    if r.take(MAGIC.len())? != MAGIC {
+        return Err(InkErr::InvalidBytecode);
+    }
+    let version = r.u32()?;
+    if version != FORMAT_VERSION {
+        return Err(InkErr::UnsupportedBytecodeVersion(version));
+    }
+
+    let block_count = r.len()?;
+    let mut prog = Vec::<Block>::new();
+    for _ in 0..block_count {
+        prog.push(r.block()?);
+    }
+
+    if r.idx != bytes.len() {
+        return Err(InkErr::InvalidBytecode);
+    }
+    return Ok(prog);
+}
+
+// tags for encoded values
+const VAL_EMPTY: u8 = 0;
+const VAL_NUMBER: u8 = 1;
+const VAL_STR: u8 = 2;
+const VAL_BOOL: u8 = 3;
+const VAL_NULL: u8 = 4;
+const VAL_FUNC: u8 = 5;
+const VAL_NATIVE_FUNC: u8 = 6;
+
+// tags for encoded ops
+const OP_NOP: u8 = 0;
+const OP_MOV: u8 = 1;
+const OP_ESCAPE: u8 = 2;
+const OP_LOAD_CONST: u8 = 3;
+const OP_LOAD_ESC: u8 = 4;
+const OP_CALL: u8 = 5;
+const OP_CALL_IF_EQ: u8 = 6;
+const OP_MAKE_COMP: u8 = 7;
+const OP_SET_COMP: u8 = 8;
+const OP_GET_COMP: u8 = 9;
+const OP_NEG: u8 = 10;
+const OP_ADD: u8 = 11;
+const OP_SUB: u8 = 12;
+const OP_MUL: u8 = 13;
+const OP_DIV: u8 = 14;
+const OP_MOD: u8 = 15;
+const OP_GTR: u8 = 16;
+const OP_LSS: u8 = 17;
+const OP_EQL: u8 = 18;
+const OP_AND: u8 = 19;
+const OP_OR: u8 = 20;
+const OP_XOR: u8 = 21;
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.buf.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn len(&mut self, n: usize) {
+        self.u32(n as u32);
+    }
+
+    fn bytes(&mut self, s: &[u8]) {
+        self.len(s.len());
+        self.buf.extend_from_slice(s);
+    }
+
+    fn block(&mut self, block: &Block) -> Result<(), InkErr> {
+        self.bytes(block.name.as_bytes());
+        self.len(block.slots);
+
+        self.len(block.consts.len());
+        for val in block.consts.iter() {
+            self.val(val)?;
+        }
+
+        self.len(block.binds_names.len());
+        for name in block.binds_names.iter() {
+            self.bytes(name.as_bytes());
+        }
+
+        self.len(block.binds.len());
+        for reg in block.binds.iter() {
+            self.len(*reg);
+        }
+
+        self.len(block.code.len());
+        for inst in block.code.iter() {
+            self.inst(inst);
+        }
+
+        return Ok(());
+    }
+
+    fn val(&mut self, val: &Val) -> Result<(), InkErr> {
+        match val {
+            Val::Empty => self.u8(VAL_EMPTY),
+            Val::Number(n) => {
+                self.u8(VAL_NUMBER);
+                self.buf.extend_from_slice(&n.to_le_bytes());
+            }
+            Val::Str(s) => {
+                self.u8(VAL_STR);
+                self.bytes(s);
+            }
+            Val::Bool(b) => {
+                self.u8(VAL_BOOL);
+                self.u8(*b as u8);
+            }
+            Val::Null => self.u8(VAL_NULL),
+            Val::Func(block_idx, heap_vals) => {
+                self.u8(VAL_FUNC);
+                self.len(*block_idx);
+                self.len(heap_vals.len());
+                for val in heap_vals.iter() {
+                    self.val(val)?;
+                }
+            }
+            Val::NativeFunc(func) => {
+                self.u8(VAL_NATIVE_FUNC);
+                self.bytes(func.name.as_bytes());
+            }
+            // composites and heap pointers only exist at runtime,
+            // and never appear in a constant pool.
+            Val::Comp(_) | Val::Escaped(_) => return Err(InkErr::InvalidBytecode),
+        }
+        return Ok(());
+    }
+
+    fn regs(&mut self, regs: &[Reg]) {
+        for reg in regs.iter() {
+            self.len(*reg);
+        }
+    }
+
+    fn inst(&mut self, inst: &Inst) {
+        self.len(inst.dest);
+        match &inst.op {
+            Op::Nop => self.u8(OP_NOP),
+            Op::Mov(reg) => {
+                self.u8(OP_MOV);
+                self.regs(&[*reg]);
+            }
+            Op::Escape(reg) => {
+                self.u8(OP_ESCAPE);
+                self.regs(&[*reg]);
+            }
+            Op::LoadConst(idx) => {
+                self.u8(OP_LOAD_CONST);
+                self.len(*idx);
+            }
+            Op::LoadEsc(idx) => {
+                self.u8(OP_LOAD_ESC);
+                self.len(*idx);
+            }
+            Op::Call(reg, args) => {
+                self.u8(OP_CALL);
+                self.regs(&[*reg]);
+                self.len(args.len());
+                self.regs(args);
+            }
+            Op::CallIfEq(reg, a, b, skip) => {
+                self.u8(OP_CALL_IF_EQ);
+                self.regs(&[*reg, *a, *b]);
+                self.len(*skip);
+            }
+            Op::MakeComp => self.u8(OP_MAKE_COMP),
+            Op::SetComp(reg, k, v) => {
+                self.u8(OP_SET_COMP);
+                self.regs(&[*reg, *k, *v]);
+            }
+            Op::GetComp(reg, k) => {
+                self.u8(OP_GET_COMP);
+                self.regs(&[*reg, *k]);
+            }
+            Op::Neg(reg) => {
+                self.u8(OP_NEG);
+                self.regs(&[*reg]);
+            }
+            Op::Add(a, b) => self.binary(OP_ADD, *a, *b),
+            Op::Sub(a, b) => self.binary(OP_SUB, *a, *b),
+            Op::Mul(a, b) => self.binary(OP_MUL, *a, *b),
+            Op::Div(a, b) => self.binary(OP_DIV, *a, *b),
+            Op::Mod(a, b) => self.binary(OP_MOD, *a, *b),
+            Op::Gtr(a, b) => self.binary(OP_GTR, *a, *b),
+            Op::Lss(a, b) => self.binary(OP_LSS, *a, *b),
+            Op::Eql(a, b) => self.binary(OP_EQL, *a, *b),
+            Op::And(a, b) => self.binary(OP_AND, *a, *b),
+            Op::Or(a, b) => self.binary(OP_OR, *a, *b),
+            Op::Xor(a, b) => self.binary(OP_XOR, *a, *b),
+        }
+    }
+
+    fn binary(&mut self, tag: u8, a: Reg, b: Reg) {
+        self.u8(tag);
+        self.regs(&[a, b]);
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    idx: usize,
+    builtins: &'b [NativeFn],
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, n: usize) -> Result<&'b [u8], InkErr> {
+        if self.bytes.len() - self.idx < n {
+            return Err(InkErr::InvalidBytecode);
+        }
+        let taken = &self.bytes[self.idx..self.idx + n];
+        self.idx += n;
+        return Ok(taken);
+    }
+
+    fn u8(&mut self) -> Result<u8, InkErr> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn u32(&mut self) -> Result<u32, InkErr> {
+        let mut le_bytes = [0; 4];
+        le_bytes.copy_from_slice(self.take(4)?);
+        return Ok(u32::from_le_bytes(le_bytes));
+    }
+
+    fn len(&mut self) -> Result<usize, InkErr> {
+        return Ok(self.u32()? as usize);
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, InkErr> {
+        let len = self.len()?;
+        return Ok(self.take(len)?.to_vec());
+    }
+
+    fn string(&mut self) -> Result<String, InkErr> {
+        return String::from_utf8(self.bytes()?).map_err(|_| InkErr::InvalidBytecode);
+    }
+
+    fn block(&mut self) -> Result<Block, InkErr> {
+        let mut block = Block::new();
+        block.name = self.string()?;
+        block.slots = self.len()?;
+
+        let const_count = self.len()?;
+        for _ in 0..const_count {
+            block.consts.push(self.val()?);
+        }
+
+        let binds_names_count = self.len()?;
+        for _ in 0..binds_names_count {
+            block.binds_names.push(self.string()?);
+        }
+
+        let binds_count = self.len()?;
+        for _ in 0..binds_count {
+            block.binds.push(self.len()?);
+        }
+
+        let code_count = self.len()?;
+        for _ in 0..code_count {
+            block.code.push(self.inst()?);
+        }
+
+        return Ok(block);
+    }
+
+    fn val(&mut self) -> Result<Val, InkErr> {
+        let val = match self.u8()? {
+            VAL_EMPTY => Val::Empty,
+            VAL_NUMBER => {
+                let mut le_bytes = [0; 8];
+                le_bytes.copy_from_slice(self.take(8)?);
+                Val::Number(f64::from_le_bytes(le_bytes))
+            }
+            VAL_STR => Val::Str(self.bytes()?),
+            VAL_BOOL => Val::Bool(self.u8()? != 0),
+            VAL_NULL => Val::Null,
+            VAL_FUNC => {
+                let block_idx = self.len()?;
+                let heap_vals_count = self.len()?;
+                let mut heap_vals = Vec::<Val>::new();
+                for _ in 0..heap_vals_count {
+                    heap_vals.push(self.val()?);
+                }
+                Val::Func(block_idx, heap_vals)
+            }
+            VAL_NATIVE_FUNC => {
+                let name = self.string()?;
+                match self.builtins.iter().find(|builtin| builtin.name == name) {
+                    Some(builtin) => Val::NativeFunc(builtin.clone()),
+                    None => return Err(InkErr::UnknownBuiltin(name)),
+                }
+            }
+            _ => return Err(InkErr::InvalidBytecode),
+        };
+        return Ok(val);
+    }
+
+    fn reg(&mut self) -> Result<Reg, InkErr> {
+        return self.len();
+    }
+
+    fn inst(&mut self) -> Result<Inst, InkErr> {
+        let dest = self.reg()?;
+        let op = match self.u8()? {
+            OP_NOP => Op::Nop,
+            OP_MOV => Op::Mov(self.reg()?),
+            OP_ESCAPE => Op::Escape(self.reg()?),
+            OP_LOAD_CONST => Op::LoadConst(self.len()?),
+            OP_LOAD_ESC => Op::LoadEsc(self.len()?),
+            OP_CALL => {
+                let reg = self.reg()?;
+                let arg_count = self.len()?;
+                let mut args = Vec::<Reg>::new();
+                for _ in 0..arg_count {
+                    args.push(self.reg()?);
+                }
+                Op::Call(reg, args)
+            }
+            OP_CALL_IF_EQ => Op::CallIfEq(self.reg()?, self.reg()?, self.reg()?, self.len()?),
+            OP_MAKE_COMP => Op::MakeComp,
+            OP_SET_COMP => Op::SetComp(self.reg()?, self.reg()?, self.reg()?),
+            OP_GET_COMP => Op::GetComp(self.reg()?, self.reg()?),
+            OP_NEG => Op::Neg(self.reg()?),
+            OP_ADD => Op::Add(self.reg()?, self.reg()?),
+            OP_SUB => Op::Sub(self.reg()?, self.reg()?),
+            OP_MUL => Op::Mul(self.reg()?, self.reg()?),
+            OP_DIV => Op::Div(self.reg()?, self.reg()?),
+            OP_MOD => Op::Mod(self.reg()?, self.reg()?),
+            OP_GTR => Op::Gtr(self.reg()?, self.reg()?),
+            OP_LSS => Op::Lss(self.reg()?, self.reg()?),
+            OP_EQL => Op::Eql(self.reg()?, self.reg()?),
+            OP_AND => Op::And(self.reg()?, self.reg()?),
+            OP_OR => Op::Or(self.reg()?, self.reg()?),
+            OP_XOR => Op::Xor(self.reg()?, self.reg()?),
+            _ => return Err(InkErr::InvalidBytecode),
+        };
+        return Ok(Inst { dest, op });
+    }
+}
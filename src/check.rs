@@ -0,0 +1,239 @@
+// Static checks, behind `schrift check`. A program is checked without running
+// any of it, and every problem found is reported, rather than only the first.
+//
+// Syntax errors come from the parser, which recovers from each one. If a
+// program parses, names are resolved with the compiler's own scope rules to
+// find undefined names and bindings that are never used, and the syntax tree
+// is walked for builtins shadowed by new names, assignments to expressions
+// that can't be assigned to, and match clauses that can never be reached.
+
+use crate::analyze::{self, BindingKind};
+use crate::err::InkErr;
+use crate::lex::{self, Span, TokKind};
+use crate::parse::{self, Node, Resolved};
+use crate::rpc::{json_str, Json};
+use crate::source::SourceFile;
+use crate::val::NativeFn;
+use crate::visit::{self, Visitor};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub severity: Severity,
+    // code names the kind of problem, like "undefined" or "unused"
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(span: Span, code: &'static str, message: String) -> Diagnostic {
+        return Diagnostic {
+            span,
+            severity: Severity::Error,
+            code,
+            message,
+        };
+    }
+
+    fn warning(span: Span, code: &'static str, message: String) -> Diagnostic {
+        return Diagnostic {
+            span,
+            severity: Severity::Warning,
+            code,
+            message,
+        };
+    }
+
+    // json describes the diagnostic for tools, with 1-based lines and columns
+    pub fn json(&self, file: &SourceFile) -> Json {
+        let start = file.position(self.span.0);
+        let end = file.position(self.span.1);
+        return Json::Obj(vec![
+            ("file", json_str(file.name())),
+            ("line", Json::Num(start.line as f64)),
+            ("col", Json::Num(start.col as f64)),
+            ("endLine", Json::Num(end.line as f64)),
+            ("endCol", Json::Num(end.col as f64)),
+            ("severity", json_str(self.severity.name())),
+            ("code", json_str(self.code)),
+            ("message", json_str(&self.message)),
+        ]);
+    }
+}
+
+// check returns the diagnostics for a program, in the order they appear in
+// the source. Names are resolved against the given builtins.
+pub fn check(text: &str, builtins: Vec<NativeFn>) -> Vec<Diagnostic> {
+    let syntax_err =
+        |(err, span): (InkErr, Span)| Diagnostic::error(span, "syntax", err.to_string());
+    let tokens = match lex::tokenize_spanned(text) {
+        Ok(tokens) => tokens,
+        Err(err) => return vec![syntax_err(err)],
+    };
+    let mut nodes = match parse::parse_all(tokens) {
+        Ok(nodes) => nodes,
+        Err(errors) => return errors.into_iter().map(syntax_err).collect(),
+    };
+
+    let mut checker = Checker {
+        builtins: builtins
+            .iter()
+            .map(|builtin| builtin.name.clone())
+            .collect(),
+        diagnostics: vec![],
+    };
+
+    // the checker reports invalid assignments, which resolution skips, so
+    // names are resolved without the rest of the analyzer. The checker then
+    // reports the names that resolved to nothing.
+    let resolution = analyze::resolve(&mut nodes, &checker.builtins);
+    for node in nodes.iter() {
+        checker.visit(node);
+    }
+    let mut diagnostics = checker.diagnostics;
+
+    match resolution {
+        Ok(resolution) => {
+            // arguments are often unused on purpose, like in callbacks that
+            // only need some of theirs, so only other names are reported
+            for (idx, binding) in resolution.bindings.iter().enumerate() {
+                let span = match (&binding.kind, binding.span) {
+                    (BindingKind::Builtin, _) | (BindingKind::Argument, _) => continue,
+                    (_, Some(span)) => span,
+                    (_, None) => continue,
+                };
+                if !resolution.references.iter().any(|(_, b)| *b == idx) {
+                    diagnostics.push(Diagnostic::warning(
+                        span,
+                        "unused",
+                        format!("\"{}\" is defined but never used", binding.name),
+                    ));
+                }
+            }
+        }
+        Err(err) => diagnostics.push(Diagnostic::error(
+            Span::default(),
+            "analyze",
+            err.to_string(),
+        )),
+    }
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.0);
+    return diagnostics;
+}
+
+struct Checker {
+    builtins: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Visitor for Checker {
+    fn visit(&mut self, node: &Node) {
+        match node {
+            Node::BinaryExpr {
+                op: TokKind::DefineOp,
+                left,
+                right,
+            } => {
+                match &**left {
+                    Node::Ident(name, span, _) => self.check_name(name, *span),
+                    Node::BinaryExpr {
+                        op: TokKind::AccessorOp,
+                        left: _,
+                        right: _,
+                    } => self.visit(left),
+                    _ => self.diagnostics.push(Diagnostic::error(
+                        span_of(left),
+                        "invalid-assignment",
+                        "cannot assign to this expression".to_string(),
+                    )),
+                }
+                self.visit(right);
+                return;
+            }
+            Node::FnLiteral { args, body: _ } => {
+                for arg in args.iter() {
+                    if let Node::Ident(name, span, _) = arg {
+                        self.check_name(name, *span);
+                    }
+                }
+            }
+            Node::MatchExpr { cond: _, clauses } => {
+                let catch_all = clauses.iter().position(|clause| match clause {
+                    Node::MatchClause { target, expr: _ } => matches!(**target, Node::EmptyIdent),
+                    _ => false,
+                });
+                if let Some(catch_all) = catch_all {
+                    for clause in clauses[catch_all + 1..].iter() {
+                        self.diagnostics.push(Diagnostic::warning(
+                            span_of(clause),
+                            "unreachable-clause",
+                            "clause can never match, because a \"_\" clause comes before it"
+                                .to_string(),
+                        ));
+                    }
+                }
+            }
+            Node::Ident(name, span, Resolved::Undefined) => self.diagnostics.push(
+                Diagnostic::error(*span, "undefined", format!("\"{}\" is not defined", name)),
+            ),
+            _ => (),
+        }
+
+        visit::walk(self, node);
+    }
+}
+
+impl Checker {
+    // check_name checks a newly defined name
+    fn check_name(&mut self, name: &str, span: Span) {
+        if self.builtins.iter().any(|builtin| builtin == name) {
+            self.diagnostics.push(Diagnostic::warning(
+                span,
+                "shadowed-builtin",
+                format!("\"{}\" shadows the builtin function of the same name", name),
+            ));
+        }
+    }
+}
+
+// span_of returns the span from the first to the last identifier or literal
+// in a node, which covers all of it but any brackets at its ends
+fn span_of(node: &Node) -> Span {
+    let mut spans = SpanCollector { spans: vec![] };
+    spans.visit(node);
+    let start = spans.spans.iter().map(|span| span.0).min().unwrap_or(0);
+    let end = spans.spans.iter().map(|span| span.1).max().unwrap_or(start);
+    return Span(start, end);
+}
+
+struct SpanCollector {
+    spans: Vec<Span>,
+}
+
+impl Visitor for SpanCollector {
+    fn visit(&mut self, node: &Node) {
+        match node {
+            Node::Ident(_, span, _)
+            | Node::NumberLiteral(_, span)
+            | Node::StringLiteral(_, span)
+            | Node::BooleanLiteral(_, span) => self.spans.push(*span),
+            _ => visit::walk(self, node),
+        }
+    }
+}
@@ -29,6 +29,27 @@ impl Comp {
         return self.map.len();
     }
 
+    pub fn from_list(items: Vec<Val>) -> Comp {
+        let mut comp = Comp::new();
+        for (i, item) in items.into_iter().enumerate() {
+            comp.set(&Val::Number(i as f64), item);
+        }
+        return comp;
+    }
+
+    // as_list returns the values of this composite in index order if its keys are exactly the
+    // contiguous integers 0..len, which is how Ink represents lists.
+    pub fn as_list(&self) -> Option<Vec<Val>> {
+        let mut items = Vec::with_capacity(self.len());
+        for i in 0..self.len() {
+            match self.map.get(&i.to_string()) {
+                Some(val) => items.push(val.clone()),
+                None => return None,
+            }
+        }
+        return Some(items);
+    }
+
     pub fn eq(&self, other: &Comp) -> bool {
         if self.len() != other.len() {
             return false;
@@ -0,0 +1,173 @@
+// Conversions between Ink values and native Rust types, for embedders
+// moving data in and out of the runtime.
+//
+// Lists convert to and from composites keyed by contiguous integers, and
+// maps convert to composites keyed by their string keys, following the same
+// key conventions as Comp::set.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use crate::comp::Comp;
+use crate::err::InkErr;
+use crate::val::Val;
+
+impl From<f64> for Val {
+    fn from(n: f64) -> Val {
+        return Val::Number(n);
+    }
+}
+
+impl From<bool> for Val {
+    fn from(b: bool) -> Val {
+        return Val::Bool(b);
+    }
+}
+
+impl From<String> for Val {
+    fn from(s: String) -> Val {
+        return Val::Str(s.into_bytes());
+    }
+}
+
+impl From<&str> for Val {
+    fn from(s: &str) -> Val {
+        return Val::Str(s.as_bytes().to_vec());
+    }
+}
+
+impl From<Vec<u8>> for Val {
+    fn from(s: Vec<u8>) -> Val {
+        return Val::Str(s);
+    }
+}
+
+impl From<Comp> for Val {
+    fn from(comp: Comp) -> Val {
+        return Val::Comp(Rc::new(RefCell::new(comp)));
+    }
+}
+
+impl<T> From<Vec<T>> for Val
+where
+    T: Into<Val> + ListItem,
+{
+    fn from(items: Vec<T>) -> Val {
+        let vals = items.into_iter().map(|item| item.into()).collect();
+        return Comp::from_list(vals).into();
+    }
+}
+
+impl<T> From<HashMap<String, T>> for Val
+where
+    T: Into<Val>,
+{
+    fn from(entries: HashMap<String, T>) -> Val {
+        let mut comp = Comp::new();
+        for (key, val) in entries {
+            comp.set(&Val::from(key), val.into());
+        }
+        return comp.into();
+    }
+}
+
+// ListItem marks types that convert to list items, so that Vec<u8> can
+// convert to a byte string rather than a list of numbers.
+pub trait ListItem {}
+
+impl ListItem for f64 {}
+impl ListItem for bool {}
+impl ListItem for String {}
+impl ListItem for &str {}
+impl ListItem for Val {}
+impl ListItem for Comp {}
+impl<T> ListItem for Vec<T> {}
+impl<T> ListItem for HashMap<String, T> {}
+
+impl TryFrom<Val> for f64 {
+    type Error = InkErr;
+
+    fn try_from(val: Val) -> Result<f64, InkErr> {
+        return match val {
+            Val::Number(n) => Ok(n),
+            _ => Err(InkErr::ExpectedNumber),
+        };
+    }
+}
+
+impl TryFrom<Val> for bool {
+    type Error = InkErr;
+
+    fn try_from(val: Val) -> Result<bool, InkErr> {
+        return match val {
+            Val::Bool(b) => Ok(b),
+            _ => Err(InkErr::ExpectedBoolean),
+        };
+    }
+}
+
+impl TryFrom<Val> for String {
+    type Error = InkErr;
+
+    fn try_from(val: Val) -> Result<String, InkErr> {
+        return match val {
+            Val::Str(s) => String::from_utf8(s).map_err(|_| InkErr::ExpectedString),
+            _ => Err(InkErr::ExpectedString),
+        };
+    }
+}
+
+impl TryFrom<Val> for Vec<u8> {
+    type Error = InkErr;
+
+    fn try_from(val: Val) -> Result<Vec<u8>, InkErr> {
+        return match val {
+            Val::Str(s) => Ok(s),
+            _ => Err(InkErr::ExpectedString),
+        };
+    }
+}
+
+impl TryFrom<Val> for Comp {
+    type Error = InkErr;
+
+    fn try_from(val: Val) -> Result<Comp, InkErr> {
+        return match val {
+            Val::Comp(comp_rc) => Ok(comp_rc.borrow().clone()),
+            _ => Err(InkErr::ExpectedCompositeValue),
+        };
+    }
+}
+
+impl<T> TryFrom<Val> for Vec<T>
+where
+    T: TryFrom<Val, Error = InkErr> + ListItem,
+{
+    type Error = InkErr;
+
+    fn try_from(val: Val) -> Result<Vec<T>, InkErr> {
+        let comp = Comp::try_from(val)?;
+        return match comp.as_list() {
+            Some(items) => items.into_iter().map(T::try_from).collect(),
+            None => Err(InkErr::ExpectedList),
+        };
+    }
+}
+
+impl<T> TryFrom<Val> for HashMap<String, T>
+where
+    T: TryFrom<Val, Error = InkErr>,
+{
+    type Error = InkErr;
+
+    fn try_from(val: Val) -> Result<HashMap<String, T>, InkErr> {
+        let comp = Comp::try_from(val)?;
+        let mut entries = HashMap::with_capacity(comp.len());
+        for (key, val) in comp.map {
+            entries.insert(key, T::try_from(val)?);
+        }
+        return Ok(entries);
+    }
+}
@@ -0,0 +1,169 @@
+// Code coverage for Ink programs. When coverage is enabled, the VM counts how
+// many times it executes every instruction, and how many times every match
+// clause is taken, and the counts are reported in the LCOV format read by
+// genhtml and most coverage dashboards.
+//
+// Coverage is reported for source lines, using the source positions the
+// compiler records for each instruction, so programs loaded from bytecode
+// have no coverage to report. Every function body, match clause body and
+// parenthesized expression list is compiled to its own block, and every block
+// is reported as an LCOV function, so function coverage shows which of them
+// ever ran. Each match expression is an LCOV branch point, with a branch for
+// each of its clauses.
+
+use std::collections::BTreeMap;
+
+use crate::gen::{Block, Op};
+use crate::source::SourceFile;
+
+#[derive(Debug)]
+pub struct Coverage {
+    // execution counts of each instruction, by block
+    hits: Vec<Vec<u64>>,
+    // for CALL_IF_EQ instructions, the number of times the clause was taken
+    taken: Vec<Vec<u64>>,
+}
+
+// Branch is one clause of a match expression
+struct Branch {
+    line: usize,
+    match_id: usize,
+    clause: usize,
+    // None if the clause was never tested
+    taken: Option<u64>,
+}
+
+impl Coverage {
+    pub fn new(prog: &[Block]) -> Coverage {
+        let counts = || prog.iter().map(|block| vec![0; block.code.len()]).collect();
+        return Coverage {
+            hits: counts(),
+            taken: counts(),
+        };
+    }
+
+    // blocks linked into the VM after coverage was enabled, like expressions
+    // evaluated by a debugger, are not counted
+    pub fn hit(&mut self, block_idx: usize, ip: usize) {
+        if let Some(count) = self.hits.get_mut(block_idx).and_then(|b| b.get_mut(ip)) {
+            *count += 1;
+        }
+    }
+
+    pub fn take(&mut self, block_idx: usize, ip: usize) {
+        if let Some(count) = self.taken.get_mut(block_idx).and_then(|b| b.get_mut(ip)) {
+            *count += 1;
+        }
+    }
+
+    // lcov formats the coverage of a program compiled from `file` as an LCOV
+    // tracefile record for it
+    pub fn lcov(&self, prog: &[Block], file: &SourceFile) -> String {
+        let mut functions = Vec::<(usize, String, u64)>::new();
+        let mut branches = Vec::<Branch>::new();
+        let mut lines = BTreeMap::<usize, u64>::new();
+        let mut matches = 0;
+        for (block_idx, block) in prog.iter().enumerate() {
+            let hits = &self.hits[block_idx];
+            let first_line = match block.positions.iter().flatten().next() {
+                Some(pos) => file.line(*pos),
+                // blocks without source positions can't be mapped to lines
+                None => continue,
+            };
+            let name = match (block_idx, block.name.is_empty()) {
+                (0, _) => "main".to_string(),
+                (_, true) => format!("#{}", block_idx),
+                (_, false) => format!("{}#{}", block.name, block_idx),
+            };
+            functions.push((first_line, name, hits.first().copied().unwrap_or(0)));
+
+            // a line's count is the count of its most executed instruction
+            for (ip, pos) in block.positions.iter().enumerate() {
+                if let Some(pos) = pos {
+                    let count = lines.entry(file.line(*pos)).or_insert(0);
+                    *count = (*count).max(hits[ip]);
+                }
+            }
+
+            // the clauses of a match compile to CALL_IF_EQ instructions that
+            // skip the clauses after them, so the last clause skips none. A
+            // clause's target may hold another match, so open matches are kept
+            // on a stack of (match id, next clause, its skip).
+            let mut open = Vec::<(usize, usize, usize)>::new();
+            for (ip, inst) in block.code.iter().enumerate() {
+                if let Op::CallIfEq(_, _, _, skip) = inst.op {
+                    let (match_id, clause) = match open.last() {
+                        Some((match_id, clause, next_skip)) if *next_skip == skip => {
+                            let next = (*match_id, *clause);
+                            open.pop();
+                            next
+                        }
+                        _ => {
+                            matches += 1;
+                            (matches - 1, 0)
+                        }
+                    };
+                    let line = match block.positions[ip] {
+                        Some(pos) => file.line(pos),
+                        None => first_line,
+                    };
+                    branches.push(Branch {
+                        line,
+                        match_id,
+                        clause,
+                        taken: match hits[ip] {
+                            0 => None,
+                            _ => Some(self.taken[block_idx][ip]),
+                        },
+                    });
+                    if skip > 0 {
+                        open.push((match_id, clause + 1, skip - 1));
+                    }
+                }
+            }
+        }
+
+        let mut out = format!("TN:\nSF:{}\n", file.name());
+        for (line, name, _) in functions.iter() {
+            out.push_str(&format!("FN:{},{}\n", line, name));
+        }
+        for (_, name, count) in functions.iter() {
+            out.push_str(&format!("FNDA:{},{}\n", count, name));
+        }
+        out.push_str(&format!("FNF:{}\n", functions.len()));
+        out.push_str(&format!(
+            "FNH:{}\n",
+            functions.iter().filter(|(_, _, count)| *count > 0).count()
+        ));
+
+        for branch in branches.iter() {
+            let taken = match branch.taken {
+                Some(taken) => taken.to_string(),
+                None => "-".to_string(),
+            };
+            out.push_str(&format!(
+                "BRDA:{},{},{},{}\n",
+                branch.line, branch.match_id, branch.clause, taken
+            ));
+        }
+        out.push_str(&format!("BRF:{}\n", branches.len()));
+        out.push_str(&format!(
+            "BRH:{}\n",
+            branches
+                .iter()
+                .filter(|branch| matches!(branch.taken, Some(taken) if taken > 0))
+                .count()
+        ));
+
+        for (line, count) in lines.iter() {
+            out.push_str(&format!("DA:{},{}\n", line, count));
+        }
+        out.push_str(&format!("LF:{}\n", lines.len()));
+        out.push_str(&format!(
+            "LH:{}\n",
+            lines.values().filter(|count| **count > 0).count()
+        ));
+        out.push_str("end_of_record\n");
+        return out;
+    }
+}
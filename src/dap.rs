@@ -0,0 +1,532 @@
+// Debug Adapter Protocol server, behind `schrift dap`. Editors like VS Code
+// launch it as a debug adapter and talk to it over stdin and stdout, in
+// JSON messages framed by Content-Length headers.
+//
+// The server runs the program on a pausable VM. Whenever the debug Session
+// pauses the VM, the server sends a stopped event and serves requests about
+// the paused program until the client asks to continue or step. The program
+// has a single thread. Since stdout carries the protocol, the program's out()
+// output is sent to the client as output events instead.
+
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::debug::{self, Breakpoint, PauseReason, Session, Step};
+use crate::err::InkErr;
+use crate::gen::Block;
+use crate::rpc::{self, field, field_list, field_num, field_str, json_str, Json};
+use crate::runtime;
+use crate::source::SourceFile;
+use crate::val::{NativeFn, Val};
+use crate::vm::{DebugHook, Status, Vm};
+
+const THREAD_ID: usize = 1;
+
+// Conn is a connection to a client
+pub struct Conn {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: usize,
+}
+
+impl Conn {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Conn {
+        return Conn {
+            input,
+            output,
+            seq: 1,
+        };
+    }
+
+    // read returns the next message from the client, or None once the client
+    // hangs up or sends a malformed message
+    fn read(&mut self) -> Option<Val> {
+        return rpc::read_message(&mut *self.input);
+    }
+
+    fn send(&mut self, kind: &'static str, mut fields: Vec<(&'static str, Json)>) {
+        fields.insert(0, ("seq", Json::Num(self.seq as f64)));
+        fields.insert(1, ("type", json_str(kind)));
+        self.seq += 1;
+
+        rpc::write_message(&mut *self.output, &Json::Obj(fields));
+    }
+
+    fn respond(&mut self, req: &Val, body: Json) {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    Json::Num(field_num(req, "seq").unwrap_or(0) as f64),
+                ),
+                ("success", Json::Bool(true)),
+                ("command", Json::Str(field_str(req, "command"))),
+                ("body", body),
+            ],
+        );
+    }
+
+    fn respond_err(&mut self, req: &Val, message: &str) {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    Json::Num(field_num(req, "seq").unwrap_or(0) as f64),
+                ),
+                ("success", Json::Bool(false)),
+                ("command", Json::Str(field_str(req, "command"))),
+                ("message", json_str(message)),
+            ],
+        );
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send("event", vec![("event", json_str(event)), ("body", body)]);
+    }
+
+    fn output(&mut self, category: &str, text: &str) {
+        self.event(
+            "output",
+            Json::Obj(vec![
+                ("category", json_str(category)),
+                ("output", json_str(text)),
+            ]),
+        );
+    }
+}
+
+// Hook pauses the VM wherever the session says to, and remembers why
+struct Hook {
+    session: Rc<RefCell<Session>>,
+    reason: Rc<Cell<PauseReason>>,
+}
+
+impl DebugHook for Hook {
+    fn before_inst(&mut self, vm: &mut Vm) -> bool {
+        return match self.session.borrow_mut().check(vm) {
+            Some(reason) => {
+                self.reason.set(reason);
+                true
+            }
+            None => false,
+        };
+    }
+}
+
+// VarRef is something whose children the client can ask for with a
+// variables request
+enum VarRef {
+    Frame(usize),
+    Comp(Val),
+}
+
+struct Launch {
+    path: String,
+    blocks: Vec<Block>,
+    session: Rc<RefCell<Session>>,
+}
+
+// Server holds the state of a debugging session that outlives single requests
+struct Server {
+    conn: Rc<RefCell<Conn>>,
+    launch: Option<Launch>,
+    // breakpoint lines requested before the program was launched
+    pending_lines: Vec<usize>,
+    // variable references handed out since the program last paused, with
+    // reference n at index n - 1
+    refs: Vec<VarRef>,
+}
+
+// Flow tells the server what to do after handling a request
+enum Flow {
+    Wait,
+    Run,
+    Resume(Step),
+    Disconnect,
+}
+
+pub fn serve(conn: Conn) {
+    let mut server = Server {
+        conn: Rc::new(RefCell::new(conn)),
+        launch: None,
+        pending_lines: vec![],
+        refs: vec![],
+    };
+
+    // configure the session until the client is done
+    loop {
+        let req = match server.read() {
+            Some(req) => req,
+            None => return,
+        };
+        match server.handle(&req, None) {
+            Flow::Run if server.launch.is_some() => break,
+            Flow::Disconnect => return,
+            _ => (),
+        }
+    }
+
+    let reason = Rc::new(Cell::new(PauseReason::Entry));
+    let mut vm = Vm::new(server.launch.as_ref().unwrap().blocks.clone());
+    vm.set_debug_hook(Box::new(Hook {
+        session: server.session(),
+        reason: reason.clone(),
+    }));
+
+    vm.start();
+    let exit_code = 'run: loop {
+        match vm.resume() {
+            Ok(Status::Paused) => {
+                let reason = match reason.get() {
+                    PauseReason::Entry => "entry",
+                    PauseReason::Breakpoint => "breakpoint",
+                    PauseReason::Step => "step",
+                };
+                server.conn.borrow_mut().event(
+                    "stopped",
+                    Json::Obj(vec![
+                        ("reason", json_str(reason)),
+                        ("threadId", Json::Num(THREAD_ID as f64)),
+                        ("allThreadsStopped", Json::Bool(true)),
+                    ]),
+                );
+
+                loop {
+                    let req = match server.read() {
+                        Some(req) => req,
+                        None => return,
+                    };
+                    match server.handle(&req, Some(&mut vm)) {
+                        Flow::Resume(step) => {
+                            server.refs.clear();
+                            server.session().borrow_mut().resume(&vm, step);
+                            continue 'run;
+                        }
+                        Flow::Disconnect => return,
+                        _ => (),
+                    }
+                }
+            }
+            Ok(Status::Exited(_)) => break 0,
+            Err(e) => {
+                server
+                    .conn
+                    .borrow_mut()
+                    .output("stderr", &format!("program exited with error: {:?}\n", e));
+                break 1;
+            }
+        }
+    };
+
+    let mut conn = server.conn.borrow_mut();
+    conn.event(
+        "exited",
+        Json::Obj(vec![("exitCode", Json::Num(exit_code as f64))]),
+    );
+    conn.event("terminated", Json::Obj(vec![]));
+    drop(conn);
+
+    // answer requests until the client hangs up
+    loop {
+        let req = match server.read() {
+            Some(req) => req,
+            None => return,
+        };
+        if let Flow::Disconnect = server.handle(&req, None) {
+            return;
+        }
+    }
+}
+
+impl Server {
+    fn read(&self) -> Option<Val> {
+        return self.conn.borrow_mut().read();
+    }
+
+    fn session(&self) -> Rc<RefCell<Session>> {
+        return self.launch.as_ref().unwrap().session.clone();
+    }
+
+    // handle answers a request. Requests about the program's state are only
+    // answered while the program is paused, in `vm`.
+    fn handle(&mut self, req: &Val, vm: Option<&mut Vm>) -> Flow {
+        let command = field_str(req, "command");
+        let args = field(req, "arguments");
+        let conn = self.conn.clone();
+        let respond = |body: Json| conn.borrow_mut().respond(req, body);
+        let respond_err = |message: &str| conn.borrow_mut().respond_err(req, message);
+
+        match (&command[..], vm) {
+            ("initialize", _) => respond(Json::Obj(vec![
+                ("supportsConfigurationDoneRequest", Json::Bool(true)),
+                ("supportsEvaluateForHovers", Json::Bool(true)),
+            ])),
+            ("launch", _) => match self.launch(&args) {
+                Ok(()) => {
+                    respond(Json::Null);
+                    // the client may configure breakpoints once launched
+                    self.conn
+                        .borrow_mut()
+                        .event("initialized", Json::Obj(vec![]));
+                }
+                Err(message) => respond_err(&message),
+            },
+            ("setBreakpoints", _) => {
+                let lines: Vec<usize> = field_list(&args, "breakpoints")
+                    .iter()
+                    .filter_map(|bp| field_num(bp, "line"))
+                    .collect();
+                respond(Json::Obj(vec![(
+                    "breakpoints",
+                    self.set_breakpoints(lines),
+                )]));
+            }
+            ("setExceptionBreakpoints", _) => {
+                respond(Json::Obj(vec![("breakpoints", Json::Arr(vec![]))]))
+            }
+            ("configurationDone", _) => {
+                respond(Json::Null);
+                return Flow::Run;
+            }
+            ("threads", _) => respond(Json::Obj(vec![(
+                "threads",
+                Json::Arr(vec![Json::Obj(vec![
+                    ("id", Json::Num(THREAD_ID as f64)),
+                    ("name", json_str("main")),
+                ])]),
+            )])),
+            ("disconnect", _) | ("terminate", _) => {
+                respond(Json::Null);
+                return Flow::Disconnect;
+            }
+            ("stackTrace", Some(vm)) => respond(self.stack_trace(vm)),
+            ("scopes", Some(vm)) => match field_num(&args, "frameId") {
+                Some(frame_idx) if frame_idx < vm.stack().len() => {
+                    self.refs.push(VarRef::Frame(frame_idx));
+                    respond(Json::Obj(vec![(
+                        "scopes",
+                        Json::Arr(vec![Json::Obj(vec![
+                            ("name", json_str("Locals")),
+                            ("variablesReference", Json::Num(self.refs.len() as f64)),
+                            ("expensive", Json::Bool(false)),
+                        ])]),
+                    )]));
+                }
+                _ => respond_err("no such frame"),
+            },
+            ("variables", Some(vm)) => match field_num(&args, "variablesReference") {
+                Some(var_ref) if var_ref >= 1 && var_ref <= self.refs.len() => {
+                    let vars = self.variables(vm, var_ref - 1);
+                    respond(Json::Obj(vec![("variables", vars)]));
+                }
+                _ => respond_err("no such variables reference"),
+            },
+            ("evaluate", Some(vm)) => {
+                let frame_idx = field_num(&args, "frameId").unwrap_or(vm.stack().len() - 1);
+                let expr = field_str(&args, "expression");
+
+                // evaluating runs code, which must not pause the program
+                let hook = vm.take_debug_hook();
+                let result = self.session().borrow().eval(vm, frame_idx, &expr);
+                if let Some(hook) = hook {
+                    vm.set_debug_hook(hook);
+                }
+
+                match result {
+                    Ok(val) => {
+                        let var_ref = self.var_ref(&val);
+                        respond(Json::Obj(vec![
+                            ("result", Json::Str(val.to_string())),
+                            ("variablesReference", Json::Num(var_ref as f64)),
+                        ]));
+                    }
+                    Err(e) => respond_err(&e.to_string()),
+                }
+            }
+            ("continue", Some(_)) => {
+                respond(Json::Obj(vec![("allThreadsContinued", Json::Bool(true))]));
+                return Flow::Resume(Step::Continue);
+            }
+            ("next", Some(_)) => {
+                respond(Json::Null);
+                return Flow::Resume(Step::Over);
+            }
+            ("stepIn", Some(_)) => {
+                respond(Json::Null);
+                return Flow::Resume(Step::Into);
+            }
+            ("stepOut", Some(_)) => {
+                respond(Json::Null);
+                return Flow::Resume(Step::Out);
+            }
+            ("stackTrace", None)
+            | ("scopes", None)
+            | ("variables", None)
+            | ("evaluate", None)
+            | ("continue", None)
+            | ("next", None)
+            | ("stepIn", None)
+            | ("stepOut", None) => respond_err("program is not paused"),
+            _ => respond_err(&format!("unsupported request {}", command)),
+        }
+        return Flow::Wait;
+    }
+
+    fn launch(&mut self, args: &Val) -> Result<(), String> {
+        let path = field_str(args, "program");
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => return Err(format!("could not read {}: {}", path, e)),
+        };
+
+        let builtins = self.builtins();
+        let blocks = match debug::compile(&source, builtins.clone()) {
+            Ok(blocks) => blocks,
+            Err(e) => return Err(format!("could not compile {}: {:?}", path, e)),
+        };
+
+        let stop_on_entry = matches!(field(args, "stopOnEntry"), Val::Bool(true));
+        let session = Session::new(SourceFile::new(&path, &source), builtins, stop_on_entry);
+        self.launch = Some(Launch {
+            path,
+            blocks,
+            session: Rc::new(RefCell::new(session)),
+        });
+
+        let lines = std::mem::take(&mut self.pending_lines);
+        self.set_breakpoints(lines);
+        return Ok(());
+    }
+
+    // builtins are the runtime's builtins, with out() sending output events
+    fn builtins(&self) -> Vec<NativeFn> {
+        let conn = self.conn.clone();
+        let out = NativeFn::new("out", move |_ctx, args: Vec<Val>| {
+            return match args.first() {
+                Some(Val::Str(s)) => {
+                    conn.borrow_mut()
+                        .output("stdout", &String::from_utf8_lossy(s));
+                    Ok(args[0].clone())
+                }
+                Some(_) => Err(InkErr::InvalidArguments),
+                None => Err(InkErr::NotEnoughArguments),
+            };
+        });
+
+        let mut builtins = runtime::builtins();
+        for builtin in builtins.iter_mut() {
+            if builtin.name == "out" {
+                *builtin = out.clone();
+            }
+        }
+        return builtins;
+    }
+
+    // set_breakpoints replaces the line breakpoints in the program, and
+    // describes them for the client. Breakpoints are verified if there is code
+    // on their line.
+    fn set_breakpoints(&mut self, lines: Vec<usize>) -> Json {
+        let launch = match &self.launch {
+            Some(launch) => launch,
+            None => {
+                let breakpoints = lines
+                    .iter()
+                    .map(|line| {
+                        Json::Obj(vec![
+                            ("verified", Json::Bool(false)),
+                            ("line", Json::Num(*line as f64)),
+                        ])
+                    })
+                    .collect();
+                self.pending_lines = lines;
+                return Json::Arr(breakpoints);
+            }
+        };
+
+        let mut session = launch.session.borrow_mut();
+        session
+            .breakpoints
+            .retain(|bp| !matches!(bp, Breakpoint::Line(_)));
+        let mut breakpoints = Vec::<Json>::new();
+        for line in lines {
+            let verified = session.has_code(&launch.blocks, line);
+            if verified {
+                session.breakpoints.push(Breakpoint::Line(line));
+            }
+            breakpoints.push(Json::Obj(vec![
+                ("verified", Json::Bool(verified)),
+                ("line", Json::Num(line as f64)),
+            ]));
+        }
+        return Json::Arr(breakpoints);
+    }
+
+    fn stack_trace(&self, vm: &Vm) -> Json {
+        let launch = self.launch.as_ref().unwrap();
+        let session = launch.session.borrow();
+
+        // frames are listed from the top of the stack, and identified by their
+        // index in the stack
+        let mut frames = Vec::<Json>::new();
+        for (frame_idx, frame) in vm.stack().iter().enumerate().rev() {
+            frames.push(Json::Obj(vec![
+                ("id", Json::Num(frame_idx as f64)),
+                ("name", Json::Str(debug::frame_label(frame))),
+                (
+                    "source",
+                    Json::Obj(vec![("path", Json::Str(launch.path.clone()))]),
+                ),
+                ("line", Json::Num(session.line(frame).unwrap_or(0) as f64)),
+                ("column", Json::Num(1.0)),
+            ]));
+        }
+        return Json::Obj(vec![
+            ("totalFrames", Json::Num(frames.len() as f64)),
+            ("stackFrames", Json::Arr(frames)),
+        ]);
+    }
+
+    // var_ref returns a new variables reference for the children of a
+    // composite value, or 0 for other values, which have none
+    fn var_ref(&mut self, val: &Val) -> usize {
+        if let Val::Comp(_) = val {
+            self.refs.push(VarRef::Comp(val.clone()));
+            return self.refs.len();
+        }
+        return 0;
+    }
+
+    fn variables(&mut self, vm: &Vm, ref_idx: usize) -> Json {
+        let named_vals = match &self.refs[ref_idx] {
+            VarRef::Frame(frame_idx) => debug::frame_vars(vm, &vm.stack()[*frame_idx])
+                .into_iter()
+                // builtins are variables in the main block, but aren't interesting
+                .filter(|(_, val)| !matches!(val, Val::NativeFunc(_)))
+                .collect(),
+            VarRef::Comp(Val::Comp(comp_rc)) => {
+                let comp = comp_rc.borrow();
+                let mut keys: Vec<&String> = comp.map.keys().collect();
+                keys.sort();
+                keys.into_iter()
+                    .map(|key| (key.clone(), comp.map[key].clone()))
+                    .collect()
+            }
+            VarRef::Comp(_) => vec![],
+        };
+
+        let mut vars = Vec::<Json>::new();
+        for (name, val) in named_vals {
+            let var_ref = self.var_ref(&val);
+            vars.push(Json::Obj(vec![
+                ("name", Json::Str(name)),
+                ("value", Json::Str(val.to_string())),
+                ("variablesReference", Json::Num(var_ref as f64)),
+            ]));
+        }
+        return Json::Arr(vars);
+    }
+}
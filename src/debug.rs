@@ -0,0 +1,512 @@
+// Step debugging for the VM. A debug Session decides where a program running
+// under the VM's DebugHook should pause, and inspects the paused program. The
+// interactive `schrift debug` Debugger here and the DAP server in dap.rs are
+// both front ends for a Session, which pause the VM by reading commands from
+// their input until one of them resumes the program.
+//
+// Stepping works on source lines, using the source positions the compiler
+// records for each instruction, and on frames. Every function body, match
+// clause body and parenthesized expression list runs in its own frame, so
+// stepping into a call or a match clause enters a new frame, and stepping over
+// one runs it to completion.
+
+use std::io::BufRead;
+
+use crate::analyze;
+use crate::err::InkErr;
+use crate::gen::{self, Block};
+use crate::lex;
+use crate::optimize;
+use crate::parse;
+use crate::runtime;
+use crate::source::SourceFile;
+use crate::val::{NativeFn, Val};
+use crate::verify;
+use crate::vm::{DebugHook, Frame, Vm, VmContext};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    Line(usize),
+    Block(usize),
+    Func(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Continue,
+    Into,
+    Over,
+    Out,
+    Inst,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PauseReason {
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StepMode {
+    Entry,
+    Continue,
+    Inst,
+    // the frame id and line a step started from
+    Into(usize, Option<usize>),
+    Over(usize, Option<usize>, usize),
+    // the stack depth a step out started from
+    Out(usize),
+}
+
+pub struct Session {
+    file: SourceFile,
+    // builtins the program was compiled with, available to evaluated expressions
+    builtins: Vec<NativeFn>,
+
+    pub breakpoints: Vec<Breakpoint>,
+    mode: StepMode,
+
+    // for each frame on the stack, the id of the frame and the source line of
+    // its last instruction, used to find the first instruction of each line
+    frame_lines: Vec<(usize, Option<usize>)>,
+}
+
+impl Session {
+    // new creates a session for a program compiled from `file`. If
+    // stop_on_entry is set, the program pauses before its first line.
+    pub fn new(file: SourceFile, builtins: Vec<NativeFn>, stop_on_entry: bool) -> Session {
+        return Session {
+            file,
+            builtins,
+            breakpoints: vec![],
+            mode: if stop_on_entry {
+                StepMode::Entry
+            } else {
+                StepMode::Continue
+            },
+            frame_lines: vec![],
+        };
+    }
+
+    // line returns the 1-based source line of the frame's next instruction, if
+    // the compiler recorded one
+    pub fn line(&self, frame: &Frame) -> Option<usize> {
+        let pos = frame.block().positions.get(frame.ip()).copied().flatten()?;
+        return Some(self.file.line(pos));
+    }
+
+    // has_code reports whether any instruction in the program comes from a
+    // source line, so that a breakpoint there can be hit
+    pub fn has_code(&self, prog: &[Block], line: usize) -> bool {
+        return prog.iter().any(|block| {
+            block
+                .positions
+                .iter()
+                .flatten()
+                .any(|pos| self.file.line(*pos) == line)
+        });
+    }
+
+    pub fn line_count(&self) -> usize {
+        return self.file.line_count();
+    }
+
+    pub fn source_line(&self, line: usize) -> &str {
+        return self.file.line_text(line);
+    }
+
+    // check is called before every instruction, and returns why the program
+    // should pause at this instruction, if it should.
+    pub fn check(&mut self, vm: &Vm) -> Option<PauseReason> {
+        let frame = vm.stack().last().unwrap();
+        let depth = vm.stack().len();
+        let line = self.line(frame);
+
+        self.frame_lines.truncate(depth);
+        let new_line = match self.frame_lines.get(depth - 1) {
+            Some((frame_id, last_line)) => *frame_id != frame.id() || *last_line != line,
+            None => true,
+        };
+        if depth > self.frame_lines.len() {
+            self.frame_lines.resize(depth, (usize::MAX, None));
+        }
+        self.frame_lines[depth - 1] = (frame.id(), line);
+
+        for bp in self.breakpoints.iter() {
+            let hit = match bp {
+                Breakpoint::Line(bp_line) => new_line && line == Some(*bp_line),
+                Breakpoint::Block(block_idx) => frame.ip() == 0 && frame.block_idx() == *block_idx,
+                Breakpoint::Func(name) => frame.ip() == 0 && frame.block().name == *name,
+            };
+            if hit {
+                return Some(PauseReason::Breakpoint);
+            }
+        }
+
+        // line steps pause only at instructions with a known source line
+        let step_done = match self.mode {
+            StepMode::Continue => false,
+            StepMode::Inst => true,
+            StepMode::Entry => line.is_some(),
+            StepMode::Into(frame_id, from_line) => {
+                line.is_some() && (frame.id() != frame_id || line != from_line)
+            }
+            StepMode::Over(frame_id, from_line, from_depth) => {
+                line.is_some()
+                    && ((frame.id() == frame_id && line != from_line) || depth < from_depth)
+            }
+            StepMode::Out(from_depth) => line.is_some() && depth < from_depth,
+        };
+        if !step_done {
+            return None;
+        }
+        return match self.mode {
+            StepMode::Entry => Some(PauseReason::Entry),
+            _ => Some(PauseReason::Step),
+        };
+    }
+
+    // resume sets where the paused program should pause next
+    pub fn resume(&mut self, vm: &Vm, step: Step) {
+        let frame = vm.stack().last().unwrap();
+        let line = self.line(frame);
+        let depth = vm.stack().len();
+
+        self.mode = match step {
+            Step::Continue => StepMode::Continue,
+            Step::Into => StepMode::Into(frame.id(), line),
+            Step::Over => StepMode::Over(frame.id(), line, depth),
+            Step::Out => StepMode::Out(depth),
+            Step::Inst => StepMode::Inst,
+        };
+    }
+
+    // eval compiles and runs an expression as a new function in the paused VM,
+    // with the variables of the frame at `frame_idx` in the stack passed in as
+    // arguments. Variables are passed by value, so assigning to them in the
+    // expression does not change the program's variables, but mutating
+    // composite values does.
+    pub fn eval(&self, vm: &mut Vm, frame_idx: usize, expr: &str) -> Result<Val, InkErr> {
+        let mut params = Vec::<String>::new();
+        let mut args = Vec::<Val>::new();
+        let mut bind = |name: String, val: Val| {
+            if !params.contains(&name) {
+                params.push(name);
+                args.push(val);
+            }
+        };
+
+        // the frame's variables shadow top-level variables in main
+        if let Some(frame) = vm.stack().get(frame_idx) {
+            for (name, val) in frame_vars(vm, frame) {
+                bind(name, val);
+            }
+        }
+        if let Some(main_frame) = vm.stack().first().filter(|frame| frame.block_idx() == 0) {
+            for (name, val) in frame_vars(vm, main_frame) {
+                bind(name, val);
+            }
+        }
+        for builtin in self.builtins.iter() {
+            bind(builtin.name.clone(), Val::NativeFunc(builtin.clone()));
+        }
+
+        let mut nodes = parse::parse(lex::tokenize(expr)?)?;
+        if nodes.is_empty() {
+            return Ok(Val::Null);
+        }
+        analyze::analyze(&mut nodes, &params)?;
+        let blocks = optimize::optimize(gen::generate_with_params(nodes, params)?);
+        verify::verify(&blocks)?;
+
+        let main_idx = vm.link(blocks);
+        return vm.call(&Val::Func(main_idx, vec![]), args);
+    }
+}
+
+// compile runs every compiler stage over a program for debugging
+pub fn compile(source: &str, builtins: Vec<NativeFn>) -> Result<Vec<Block>, InkErr> {
+    let mut nodes = parse::parse(lex::tokenize(source)?)?;
+    let names: Vec<String> = builtins
+        .iter()
+        .map(|builtin| builtin.name.clone())
+        .collect();
+    analyze::analyze(&mut nodes, &names)?;
+    let blocks = optimize::optimize(gen::generate(nodes, builtins)?);
+    verify::verify(&blocks)?;
+    return Ok(blocks);
+}
+
+pub fn frame_label(frame: &Frame) -> String {
+    let block = frame.block();
+    return match (frame.block_idx(), block.name.is_empty()) {
+        (0, _) => "main".to_string(),
+        (block_idx, true) => format!("#{}", block_idx),
+        (block_idx, false) => format!("{}#{}", block.name, block_idx),
+    };
+}
+
+// frame_vars lists the variables visible in a frame with their current values.
+// A function reads a captured variable into a local register the first time it
+// uses it, so until then the value comes from the frame's binds.
+pub fn frame_vars(vm: &Vm, frame: &Frame) -> Vec<(String, Val)> {
+    let block = frame.block();
+    let mut vars = Vec::<(String, Val)>::new();
+    for (name, reg) in block.locals.iter() {
+        let val = vm.resolve(&frame.regs()[*reg]);
+        let bind_idx = block.binds_names.iter().position(|bind| bind == name);
+        match (val, bind_idx) {
+            (Val::Empty, Some(bind_idx)) => {
+                vars.push((name.clone(), vm.resolve(&frame.binds()[bind_idx]).clone()))
+            }
+            _ => vars.push((name.clone(), val.clone())),
+        }
+    }
+    for (name, val) in block.binds_names.iter().zip(frame.binds()) {
+        if !block.locals.iter().any(|(local, _)| local == name) {
+            vars.push((name.clone(), vm.resolve(val).clone()));
+        }
+    }
+    return vars;
+}
+
+const HELP: &str = "commands:
+  s, step            run to the next line, stepping into calls
+  n, next            run to the next line in this frame, stepping over calls
+  o, out             run until this frame returns
+  si, stepi          run one instruction
+  c, continue        run until the next breakpoint
+  b, break <line>    pause at a source line
+  b, break #<block>  pause on entering a block
+  b, break <name>    pause on entering a function with the given name
+  d, delete [n]      delete breakpoint n, or all breakpoints
+  bl, breakpoints    list breakpoints
+  l, list            show source around the current line
+  bt, stack          show the call stack
+  v, vars            show variables in the current frame
+  r, regs            show the current frame's registers
+  heap               show escaped values on the heap
+  p, print <expr>    evaluate an expression in the current frame's scope
+  q, quit            stop the program and exit";
+
+// Debugger is the interactive command line debugger behind `schrift debug`
+pub struct Debugger {
+    session: Session,
+    input: Box<dyn BufRead>,
+}
+
+impl Debugger {
+    // new creates a debugger for a program compiled from `file`, which is
+    // paused before its first line.
+    pub fn new(file: SourceFile, input: Box<dyn BufRead>) -> Debugger {
+        return Debugger {
+            session: Session::new(file, runtime::builtins(), true),
+            input,
+        };
+    }
+
+    fn print_location(&self, frame: &Frame) {
+        let block = frame.block();
+        match self.session.line(frame) {
+            Some(line) => println!(
+                "{}, line {}: {}",
+                frame_label(frame),
+                line,
+                self.session.source_line(line).trim()
+            ),
+            None => println!("{}", frame_label(frame)),
+        }
+        println!("  {}\t{}", frame.ip(), block.code[frame.ip()]);
+    }
+
+    fn print_list(&self, frame: &Frame) {
+        let line = match self.session.line(frame) {
+            Some(line) => line,
+            None => {
+                println!("no source position for this instruction");
+                return;
+            }
+        };
+        let first = if line > 5 { line - 5 } else { 1 };
+        for l in first..=(line + 5).min(self.session.line_count()) {
+            let marker = if l == line { ">" } else { " " };
+            println!("{} {:>4}  {}", marker, l, self.session.source_line(l));
+        }
+    }
+
+    fn print_stack(&self, vm: &Vm) {
+        for (depth, frame) in vm.stack().iter().enumerate().rev() {
+            let line = match self.session.line(frame) {
+                Some(line) => format!(", line {}", line),
+                None => String::new(),
+            };
+            println!(
+                "{:>3}  {} at instruction {}{}",
+                depth,
+                frame_label(frame),
+                frame.ip(),
+                line
+            );
+        }
+    }
+
+    fn print_vars(&self, vm: &Vm, frame: &Frame) {
+        for (name, val) in frame_vars(vm, frame) {
+            // builtins are variables in the main block, but aren't interesting
+            if let Val::NativeFunc(_) = val {
+                continue;
+            }
+            println!("  {} = {}", name, val);
+        }
+    }
+
+    fn print_regs(&self, vm: &Vm, frame: &Frame) {
+        for (reg, val) in frame.regs().iter().enumerate() {
+            match val {
+                Val::Escaped(heap_idx) => {
+                    println!("  @{}\t{} (heap {})", reg, vm.resolve(val), heap_idx)
+                }
+                _ => println!("  @{}\t{}", reg, val),
+            }
+        }
+    }
+
+    fn add_breakpoint(&mut self, vm: &Vm, arg: &str) {
+        let bp = if let Some(block_str) = arg.strip_prefix('#') {
+            match block_str.parse::<usize>() {
+                Ok(block_idx) if block_idx < vm.prog().len() => Breakpoint::Block(block_idx),
+                _ => {
+                    println!("no block {}", arg);
+                    return;
+                }
+            }
+        } else if let Ok(line) = arg.parse::<usize>() {
+            Breakpoint::Line(line)
+        } else if vm.prog().iter().any(|block| block.name == arg) {
+            Breakpoint::Func(arg.to_string())
+        } else {
+            println!("no function named {}", arg);
+            return;
+        };
+
+        self.session.breakpoints.push(bp);
+        println!("breakpoint {} at {}", self.session.breakpoints.len(), arg);
+    }
+
+    fn delete_breakpoint(&mut self, arg: &str) {
+        let breakpoints = &mut self.session.breakpoints;
+        if arg.is_empty() {
+            breakpoints.clear();
+            return;
+        }
+        match arg.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= breakpoints.len() => {
+                breakpoints.remove(n - 1);
+            }
+            _ => println!("no breakpoint {}", arg),
+        }
+    }
+
+    fn print_breakpoints(&self) {
+        for (i, bp) in self.session.breakpoints.iter().enumerate() {
+            match bp {
+                Breakpoint::Line(line) => println!("  {}  line {}", i + 1, line),
+                Breakpoint::Block(block_idx) => println!("  {}  #{}", i + 1, block_idx),
+                Breakpoint::Func(name) => println!("  {}  {}", i + 1, name),
+            }
+        }
+    }
+
+    // prompt reads and runs commands until one resumes the program
+    fn prompt(&mut self, vm: &mut Vm) {
+        loop {
+            print!("(debug) ");
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+
+            let mut input = String::new();
+            match self.input.read_line(&mut input) {
+                Ok(0) | Err(_) => std::process::exit(0),
+                Ok(_) => (),
+            }
+            let input = input.trim();
+            let (cmd, arg) = match input.find(' ') {
+                Some(idx) => (&input[..idx], input[idx..].trim()),
+                None => (input, ""),
+            };
+
+            // commands that don't resume the program continue the loop
+            let step = match cmd {
+                "s" | "step" => Step::Into,
+                "n" | "next" => Step::Over,
+                "o" | "out" => Step::Out,
+                "si" | "stepi" => Step::Inst,
+                "c" | "continue" => Step::Continue,
+                "b" | "break" => {
+                    self.add_breakpoint(vm, arg);
+                    continue;
+                }
+                "d" | "delete" => {
+                    self.delete_breakpoint(arg);
+                    continue;
+                }
+                "bl" | "breakpoints" => {
+                    self.print_breakpoints();
+                    continue;
+                }
+                "l" | "list" => {
+                    self.print_list(vm.stack().last().unwrap());
+                    continue;
+                }
+                "bt" | "stack" => {
+                    self.print_stack(vm);
+                    continue;
+                }
+                "v" | "vars" => {
+                    self.print_vars(vm, vm.stack().last().unwrap());
+                    continue;
+                }
+                "r" | "regs" => {
+                    self.print_regs(vm, vm.stack().last().unwrap());
+                    continue;
+                }
+                "heap" => {
+                    for (heap_idx, val) in vm.heap().iter().enumerate() {
+                        println!("  {}\t{}", heap_idx, val);
+                    }
+                    continue;
+                }
+                "p" | "print" => {
+                    let frame_idx = vm.stack().len() - 1;
+                    match self.session.eval(vm, frame_idx, arg) {
+                        Ok(val) => println!("{}", val),
+                        Err(e) => println!("error: {}", e),
+                    }
+                    continue;
+                }
+                "q" | "quit" => std::process::exit(0),
+                "h" | "help" => {
+                    println!("{}", HELP);
+                    continue;
+                }
+                "" => continue,
+                _ => {
+                    println!("unknown command {}, try help", cmd);
+                    continue;
+                }
+            };
+            self.session.resume(vm, step);
+            return;
+        }
+    }
+}
+
+impl DebugHook for Debugger {
+    fn before_inst(&mut self, vm: &mut Vm) -> bool {
+        if self.session.check(vm).is_some() {
+            self.print_location(vm.stack().last().unwrap());
+            self.prompt(vm);
+        }
+        return false;
+    }
+}
@@ -1,17 +1,22 @@
+use std::error;
+use std::fmt;
+
 #[derive(Debug)]
 pub enum InkErr {
     // lexer errors
     InvalidNumber(String),
+    UnexpectedCharacter(char),
+    InvalidEscape(String),
     // parser errors
     UnexpectedEOF,
-    UnexpectedToken,
+    UnexpectedToken { expected: String, found: String },
+    TooDeeplyNested(usize),
     ExpectedCompositeValue,
-    ExpectedMatchCaseArrow,
-    UnexpectedArgument,
     // analyzer errors
     UndefinedVariable,
     // compiler errors
     InvalidAssignment,
+    UnexpectedNode(String),
     // runtime errors
     InvalidOperand,
     InvalidFunctionCall,
@@ -22,4 +27,36 @@ pub enum InkErr {
     ExpectedIntegerIndex,
     IndexOutOfBounds,
     ExpectedString,
+    StackOverflow(usize),
+    BudgetExceeded,
+    // conversion errors
+    ExpectedNumber,
+    ExpectedBoolean,
+    ExpectedList,
+    SerdeError(String),
+    // bytecode file errors
+    InvalidBytecode,
+    UnsupportedBytecodeVersion(u32),
+    UnknownBuiltin(String),
+    // verifier errors
+    VerificationFailed(String),
+    // assembler errors
+    AssemblyError(String),
 }
+
+impl fmt::Display for InkErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InkErr::UnexpectedToken { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            InkErr::UnexpectedEOF => write!(f, "unexpected end of program"),
+            InkErr::TooDeeplyNested(max) => {
+                write!(f, "expression nested more than {} deep", max)
+            }
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl error::Error for InkErr {}
@@ -0,0 +1,280 @@
+// Source formatter, behind `schrift fmt`. A program is parsed with its comments
+// kept as trivia on the nodes around them, and printed back as canonical Ink:
+// one tab of indentation for each level of brackets, single spaces around
+// binary operators and after commas, and one item per line in blocks.
+//
+// The formatter only decides where lines break inside brackets, and keeps
+// everything else about the layout of a program that it can. Brackets are
+// split over lines when they were split in the source, and their items are
+// printed on their own lines, or after a comma on the line of the item before
+// them when they were there in the source. Otherwise everything between the
+// brackets is printed on one line. Blank lines between items are kept, but
+// never more than one in a row.
+
+use crate::err::InkErr;
+use crate::lex::{self, Span, TokKind};
+use crate::parse::{self, Comment, Node};
+
+// format returns the canonical form of a program, or its syntax errors
+pub fn format(text: &str) -> Result<String, Vec<(InkErr, Span)>> {
+    let tokens = match lex::tokenize_spanned(text) {
+        Ok(tokens) => tokens,
+        Err(err) => return Err(vec![err]),
+    };
+    let (nodes, comments) = parse::parse_with_comments(tokens)?;
+
+    let formatter = Formatter { text };
+    let mut out = String::new();
+    let mut prev_end = formatter.write_items(&mut out, &nodes, 0);
+    for comment in comments.iter() {
+        prev_end = formatter.write_comment(&mut out, comment, prev_end, 0);
+    }
+
+    // every item and comment is written after a newline, and the first one
+    // is at the start of the program
+    if out.is_empty() {
+        return Ok(out);
+    }
+    out.remove(0);
+    out.push('\n');
+    return Ok(out);
+}
+
+struct Formatter<'a> {
+    text: &'a str,
+}
+
+impl<'a> Formatter<'a> {
+    fn node(&self, node: &Node, depth: usize) -> String {
+        return match node {
+            Node::UnaryExpr { op: _, arg } => format!("~{}", self.node(arg, depth)),
+            Node::BinaryExpr {
+                op: TokKind::AccessorOp,
+                left,
+                right,
+            } => format!("{}.{}", self.node(left, depth), self.node(right, depth)),
+            Node::BinaryExpr { op, left, right } => format!(
+                "{} {} {}",
+                self.node(left, depth),
+                operator(op),
+                self.node(right, depth)
+            ),
+            Node::FnCall { func, args } => {
+                format!(
+                    "{}{}",
+                    self.node(func, depth),
+                    self.list("(", ")", args, depth)
+                )
+            }
+            Node::MatchClause { target, expr } => {
+                format!("{} -> {}", self.node(target, depth), self.node(expr, depth))
+            }
+            Node::MatchExpr { cond, clauses } => format!(
+                "{} :: {}",
+                self.node(cond, depth),
+                self.list("{", "}", clauses, depth)
+            ),
+            Node::ExprList(exprs) => self.list("(", ")", exprs, depth),
+            Node::EmptyIdent => "_".to_string(),
+            Node::Ident(name, _, _) => name.clone(),
+            Node::NumberLiteral(num, _) => num.to_string(),
+            Node::StringLiteral(s, _) => format!("'{}'", s),
+            Node::BooleanLiteral(b, _) => b.to_string(),
+            Node::ObjectLiteral(entries) => self.list("{", "}", entries, depth),
+            Node::ObjectEntry { key, val } => {
+                format!("{}: {}", self.node(key, depth), self.node(val, depth))
+            }
+            Node::ListLiteral(items) => self.list("[", "]", items, depth),
+            Node::FnLiteral { args, body } => {
+                let body = self.node(body, depth);
+                match &args[..] {
+                    [arg] => format!("{} => {}", self.node(arg, depth), body),
+                    _ => {
+                        let args: Vec<String> =
+                            args.iter().map(|arg| self.node(arg, depth)).collect();
+                        format!("({}) => {}", args.join(", "), body)
+                    }
+                }
+            }
+            Node::Trivia { node, .. } => self.node(node, depth),
+        };
+    }
+
+    // list prints the items between brackets on one line, unless they were
+    // split over lines in the source or have comments around them
+    fn list(&self, open: &str, close: &str, items: &[Node], depth: usize) -> String {
+        if items.is_empty() {
+            return format!("{}{}", open, close);
+        }
+        if self.is_split(items) {
+            return self.block(open, close, items, depth);
+        }
+
+        let items: Vec<String> = items.iter().map(|item| self.node(item, depth)).collect();
+        return format!("{}{}{}", open, items.join(", "), close);
+    }
+
+    // block prints the items between brackets one to a line, indented one
+    // level deeper than the line the block starts on
+    fn block(&self, open: &str, close: &str, items: &[Node], depth: usize) -> String {
+        if items.is_empty() {
+            return format!("{}{}", open, close);
+        }
+
+        let mut out = open.to_string();
+        self.write_items(&mut out, items, depth + 1);
+        out.push('\n');
+        out.push_str(&indent(depth));
+        out.push_str(close);
+        return out;
+    }
+
+    // is_split reports whether a newline or comment comes before any item
+    fn is_split(&self, items: &[Node]) -> bool {
+        let mut prev_end: Option<usize> = None;
+        for item in items.iter() {
+            let (span, before, after) = match item {
+                Node::Trivia {
+                    node: _,
+                    span,
+                    before,
+                    after,
+                } => (span, before, after),
+                _ => return false,
+            };
+            if !before.is_empty() || !after.is_empty() {
+                return true;
+            }
+
+            // the first item may follow a newline after the opening bracket
+            let newline = match prev_end {
+                Some(end) => self.text[end..span.0].contains('\n'),
+                None => self.text[..span.0]
+                    .trim_end_matches([' ', '\t', '\r'])
+                    .ends_with('\n'),
+            };
+            if newline {
+                return true;
+            }
+            prev_end = Some(span.1);
+        }
+        return false;
+    }
+
+    // write_items writes each item on a new line at the given depth, with
+    // the comments around it, and returns the end of the last one in the
+    // source
+    fn write_items(&self, out: &mut String, items: &[Node], depth: usize) -> Option<usize> {
+        let mut prev_end: Option<usize> = None;
+        for item in items.iter() {
+            match item {
+                Node::Trivia {
+                    node,
+                    span,
+                    before,
+                    after,
+                } => {
+                    for comment in before.iter() {
+                        prev_end = self.write_comment(out, comment, prev_end, depth);
+                    }
+                    // a comma may only follow the item before, not a comment
+                    match prev_end {
+                        Some(end)
+                            if before.is_empty() && !self.text[end..span.0].contains('\n') =>
+                        {
+                            out.push_str(", ")
+                        }
+                        _ => self.write_newline(out, prev_end, span.0, depth),
+                    }
+                    out.push_str(&self.node(node, depth));
+                    prev_end = Some(span.1);
+                    for comment in after.iter() {
+                        prev_end = self.write_comment(out, comment, prev_end, depth);
+                    }
+                }
+                _ => {
+                    self.write_newline(out, None, 0, depth);
+                    out.push_str(&self.node(item, depth));
+                }
+            }
+        }
+        return prev_end;
+    }
+
+    // write_comment writes a comment on a new line, or at the end of the
+    // line before it if it was there in the source. Block comments always
+    // get their own line, because a newline right after one does not end the
+    // expression before it.
+    fn write_comment(
+        &self,
+        out: &mut String,
+        comment: &Comment,
+        prev_end: Option<usize>,
+        depth: usize,
+    ) -> Option<usize> {
+        let is_line = !self.text[comment.span.1..].starts_with('`');
+        let trailing = match prev_end {
+            Some(end) => end <= comment.span.0 && !self.text[end..comment.span.0].contains('\n'),
+            None => false,
+        };
+
+        if is_line {
+            if trailing {
+                out.push(' ');
+            } else {
+                self.write_newline(out, prev_end, comment.span.0, depth);
+            }
+            out.push_str("``");
+            out.push_str(comment.text.trim_end());
+        } else {
+            self.write_newline(out, prev_end, comment.span.0, depth);
+            out.push('`');
+            out.push_str(&comment.text);
+            out.push('`');
+        }
+        return Some(comment.span.1);
+    }
+
+    // write_newline starts a new line at the given depth, after a blank line
+    // if there was one between the end of the last item and `start`
+    fn write_newline(&self, out: &mut String, prev_end: Option<usize>, start: usize, depth: usize) {
+        if let Some(end) = prev_end {
+            if end <= start && has_blank_line(&self.text[end..start]) {
+                out.push('\n');
+            }
+        }
+        out.push('\n');
+        out.push_str(&indent(depth));
+    }
+}
+
+fn has_blank_line(gap: &str) -> bool {
+    let lines: Vec<&str> = gap.split('\n').collect();
+    return lines.len() > 2
+        && lines[1..lines.len() - 1]
+            .iter()
+            .any(|line| line.trim().is_empty());
+}
+
+fn indent(depth: usize) -> String {
+    return "\t".repeat(depth);
+}
+
+fn operator(op: &TokKind) -> &'static str {
+    return match op {
+        TokKind::AddOp => "+",
+        TokKind::SubOp => "-",
+        TokKind::MulOp => "*",
+        TokKind::DivOp => "/",
+        TokKind::ModOp => "%",
+        TokKind::GtOp => ">",
+        TokKind::LtOp => "<",
+        TokKind::EqOp => "=",
+        TokKind::AndOp => "&",
+        TokKind::OrOp => "|",
+        TokKind::XorOp => "^",
+        TokKind::DefineOp => ":=",
+        TokKind::AccessorOp => ".",
+        _ => "?",
+    };
+}
@@ -1,12 +1,11 @@
 use std::fmt;
 
 use crate::err::InkErr;
-use crate::lex::TokKind;
-use crate::parse::Node;
-use crate::runtime;
+use crate::lex::{self, TokKind};
+use crate::parse::{Node, Resolved};
 use crate::val::{NativeFn, Val};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub type Reg = usize;
 
@@ -53,9 +52,12 @@ impl fmt::Display for Op {
             Op::LoadEsc(idx) => write!(f, "LOAD_ESC {}", idx),
             Op::Call(reg, args) => write!(
                 f,
-                "CALL @{}, [{:?}]",
+                "CALL @{}, [{}]",
                 reg,
-                args.iter().map(|r| format!("@{}", r)).collect::<String>()
+                args.iter()
+                    .map(|r| format!("@{}", r))
+                    .collect::<Vec<String>>()
+                    .join(", ")
             ),
             Op::CallIfEq(reg, a, b, jump_by) => {
                 write!(f, "CALL_IF_EQ @{}, @{} == @{}, {}", reg, a, b, jump_by)
@@ -91,96 +93,124 @@ impl fmt::Display for Inst {
     }
 }
 
-#[derive(Debug, Clone)]
-struct ScopeRecord {
-    reg: Reg,
-    from_current_scope: bool,
-    forward_decl: bool,
-    escaped: bool,
+// Scope is what the code generator knows about the names a block uses. Which
+// binding each name refers to is already resolved by the analyzer.
+#[derive(Default)]
+struct Scope {
+    // registers holding the names defined in the scope
+    locals: HashMap<String, Reg>,
+    // registers holding names captured from scopes around this one, with how
+    // many scopes out each is defined, in the order of the block's binds
+    captures: Vec<(String, usize, Reg)>,
+    // registers holding the names the main scope defines before the program,
+    // even once the program defines the same names, and the names whose
+    // registers have not been moved to the heap yet
+    predefined: HashMap<String, Reg>,
+    unescaped: HashSet<String>,
 }
 
 struct ScopeStack {
-    scopes: Vec<HashMap<String, ScopeRecord>>,
+    scopes: Vec<Scope>,
 }
 
 impl ScopeStack {
     fn new() -> ScopeStack {
         return ScopeStack {
-            scopes: vec![HashMap::new()],
+            scopes: vec![Scope::default()],
         };
     }
 
     fn push(&mut self) {
-        self.scopes.push(HashMap::new());
+        self.scopes.push(Scope::default());
     }
 
-    fn pop(&mut self) {
-        self.scopes.pop();
+    fn pop(&mut self) -> Scope {
+        return self.scopes.pop().unwrap();
     }
 
-    fn last(&self) -> &HashMap<String, ScopeRecord> {
-        return self.scopes.last().unwrap();
+    // depth is the number of scopes around the innermost one
+    fn depth(&self) -> usize {
+        return self.scopes.len() - 1;
     }
 
-    fn get(&mut self, name: &String) -> Option<ScopeRecord> {
-        for (i, scope) in self.scopes.iter_mut().rev().enumerate() {
-            match scope.get_mut(name) {
-                Some(rec) if rec.from_current_scope => {
-                    let escaped = i > 0;
-                    if escaped {
-                        rec.escaped = true;
-                    }
-                    return Some(ScopeRecord {
-                        reg: rec.reg,
-                        from_current_scope: i == 0,
-                        forward_decl: rec.forward_decl,
-                        escaped: rec.escaped,
-                    });
-                }
-                _ => {
-                    scope.insert(
-                        name.to_string(),
-                        ScopeRecord {
-                            reg: 0, // dummy reg
-                            from_current_scope: false,
-                            forward_decl: false,
-                            escaped: true,
-                        },
-                    );
-                }
-            };
+    // local returns the register holding a name defined in the innermost scope
+    fn local(&self, name: &str) -> Option<Reg> {
+        return self.scopes.last().unwrap().locals.get(name).copied();
+    }
+
+    fn insert(&mut self, name: String, reg: Reg) {
+        self.scopes.last_mut().unwrap().locals.insert(name, reg);
+    }
+
+    // forward_declare returns the register of a name defined as an item of
+    // the innermost scope. A name the scope already defines keeps its
+    // register, like an argument redefined in the body of its function, but
+    // names defined before the program are replaced by a new one.
+    fn forward_declare(&mut self, name: &str, block: &mut Block) -> (Reg, bool) {
+        let scope = self.scopes.last_mut().unwrap();
+        if let Some(reg) = scope.locals.get(name) {
+            if scope.predefined.get(name) != Some(reg) {
+                return (*reg, false);
+            }
         }
 
-        return None;
+        let reg = block.iota();
+        scope.unescaped.remove(name);
+        scope.locals.insert(name.to_string(), reg);
+        return (reg, true);
     }
 
-    fn insert(&mut self, name: String, reg: Reg) {
-        self.scopes.last_mut().unwrap().insert(
-            name,
-            ScopeRecord {
-                reg,
-                from_current_scope: true,
-                forward_decl: false,
-                escaped: false,
-            },
-        );
+    // predefine defines a name in the main scope before the program
+    fn predefine(&mut self, name: String, reg: Reg) {
+        self.scopes[0].predefined.insert(name.clone(), reg);
+        self.scopes[0].unescaped.insert(name.clone());
+        self.scopes[0].locals.insert(name, reg);
     }
 
-    fn forward_declare(&mut self, name: String, reg: Reg) {
-        self.scopes.last_mut().unwrap().insert(
-            name,
-            ScopeRecord {
-                reg,
-                from_current_scope: true,
-                forward_decl: true,
-                escaped: false,
-            },
-        );
+    // predefined returns the register holding a name the main scope defines
+    // before the program
+    fn predefined(&self, name: &str) -> Option<Reg> {
+        return self.scopes[0].predefined.get(name).copied();
+    }
+
+    // captured returns the register a captured name is already loaded into
+    // in the innermost scope
+    fn captured(&self, name: &str) -> Option<Reg> {
+        let scope = self.scopes.last().unwrap();
+        return scope
+            .captures
+            .iter()
+            .find(|(captured, _, _)| captured == name)
+            .map(|(_, _, reg)| *reg);
+    }
+
+    fn capture(&mut self, name: String, depth: usize, reg: Reg) {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.captures.push((name, depth, reg));
+    }
+
+    // locals lists the names used in the innermost scope, with the registers
+    // that hold them at the end of the scope.
+    fn locals(&self) -> Vec<(String, Reg)> {
+        let scope = self.scopes.last().unwrap();
+        let mut locals: HashMap<String, Reg> = scope
+            .captures
+            .iter()
+            .map(|(name, _, reg)| (name.clone(), *reg))
+            .collect();
+        locals.extend(scope.locals.iter().map(|(name, reg)| (name.clone(), *reg)));
+
+        let mut locals: Vec<(String, Reg)> = locals.into_iter().collect();
+        locals.sort();
+        return locals;
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Block {
+    // name is the name a function literal was defined with, as in `name := () => ...`,
+    // or empty for anonymous functions and the main block.
+    pub name: String,
     pub slots: usize,
     pub consts: Vec<Val>,
 
@@ -191,20 +221,83 @@ pub struct Block {
     pub binds: Vec<Reg>,
     pub code: Vec<Inst>,
 
+    // Debug information, which is not saved in bytecode. positions holds the
+    // source offset of the expression each instruction was generated for, and
+    // locals the register that holds each variable defined in the block.
+    pub positions: Vec<Option<usize>>,
+    pub locals: Vec<(String, Reg)>,
+    pos: Option<usize>,
+
     // integer counter to label autoincremented
     // pseudo-register allocations.
     iota: usize,
     parent: Option<Box<Block>>,
 }
 
+// fmt_const formats a constant unambiguously, so that block listings can be
+// read back by the assembler in asm.rs.
+fn fmt_const(f: &mut fmt::Formatter, val: &Val) -> fmt::Result {
+    match val {
+        Val::Str(s) => {
+            write!(f, "'")?;
+            match std::str::from_utf8(s) {
+                Ok(text) => {
+                    for c in text.chars() {
+                        match c {
+                            _ if c.is_ascii() => fmt_const_byte(f, c as u8)?,
+                            _ => write!(f, "{}", c)?,
+                        }
+                    }
+                }
+                // byte strings that aren't valid UTF-8 are written byte by byte
+                Err(_) => {
+                    for b in s.iter() {
+                        fmt_const_byte(f, *b)?;
+                    }
+                }
+            }
+            write!(f, "'")
+        }
+        Val::Func(block_idx, heap_vals) => {
+            write!(f, "Func({}, [", block_idx)?;
+            for (i, v) in heap_vals.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                fmt_const(f, v)?;
+            }
+            write!(f, "])")
+        }
+        Val::NativeFunc(func) => write!(f, "NativeFunc({})", func.name),
+        // values print negative zero as 0, but 1 / ~0 is -inf, not inf
+        Val::Number(n) if *n == 0.0 && n.is_sign_negative() => write!(f, "-0"),
+        _ => write!(f, "{}", val),
+    }
+}
+
+fn fmt_const_byte(f: &mut fmt::Formatter, b: u8) -> fmt::Result {
+    match b {
+        b'\\' => write!(f, "\\\\"),
+        b'\'' => write!(f, "\\'"),
+        b'\n' => write!(f, "\\n"),
+        b'\t' => write!(f, "\\t"),
+        0x20..=0x7e => write!(f, "{}", b as char),
+        _ => write!(f, "\\x{:02x}", b),
+    }
+}
+
 impl fmt::Display for Block {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.name.is_empty() {
+            writeln!(f, "name: {}", self.name)?;
+        }
+        writeln!(f, "slots: {}", self.slots)?;
         write!(f, "consts: [")?;
         for (i, c) in self.consts.iter().enumerate() {
             if i > 0 {
                 write!(f, ", ")?;
             }
-            write!(f, "{}", c)?
+            fmt_const(f, c)?;
         }
         writeln!(f, "]")?;
         writeln!(f, "binds: {:?}", self.binds)?;
@@ -216,13 +309,17 @@ impl fmt::Display for Block {
 }
 
 impl Block {
-    fn new() -> Block {
+    pub fn new() -> Block {
         return Block {
+            name: String::new(),
             slots: 0,
             consts: vec![],
             binds_names: vec![],
             binds: vec![],
             code: vec![],
+            positions: vec![],
+            locals: vec![],
+            pos: None,
             iota: 0,
             parent: None,
         };
@@ -234,6 +331,11 @@ impl Block {
         return last;
     }
 
+    fn emit(&mut self, inst: Inst) {
+        self.code.push(inst);
+        self.positions.push(self.pos);
+    }
+
     fn push_const(&mut self, val: Val) -> Reg {
         self.consts.push(val);
         return self.consts.len() - 1;
@@ -269,15 +371,25 @@ impl Block {
                 right: _,
             } = node
             {
-                if let Node::Ident(name) = &**define_left {
-                    scopes.forward_declare(name.clone(), self.iota());
+                if let Node::Ident(name, _, resolved) = &**define_left {
+                    let (reg, is_new) = scopes.forward_declare(name, self);
+                    // closures may capture the name before it's defined, so it
+                    // moves to the heap at the start of the scope
+                    if is_new && *resolved == (Resolved::Local { escapes: true }) {
+                        self.emit(Inst {
+                            dest: reg,
+                            op: Op::Escape(reg),
+                        });
+                    }
                 }
             }
         }
         for node in nodes.iter() {
+            self.pos = node.pos().or(self.pos);
             self.generate_node(&node, scopes, push_block)?;
         }
         self.slots = self.iota;
+        self.locals = scopes.locals();
         return Ok(());
     }
 
@@ -296,7 +408,7 @@ impl Block {
             Node::UnaryExpr { op: _, arg } => {
                 let arg_reg = self.generate_node(&arg, &mut scopes, push_block)?;
                 let dest = self.iota();
-                self.code.push(Inst {
+                self.emit(Inst {
                     dest,
                     op: Op::Neg(arg_reg),
                 });
@@ -307,7 +419,12 @@ impl Block {
                 left: define_left,
                 right: define_right,
             } => {
-                let right_reg = self.generate_node(&define_right, &mut scopes, push_block)?;
+                let right_reg = match (&**define_left, &**define_right) {
+                    (Node::Ident(name, _, _), Node::FnLiteral { args, body }) => {
+                        self.generate_fn(args, body, name, &mut scopes, push_block)?
+                    }
+                    _ => self.generate_node(&define_right, &mut scopes, push_block)?,
+                };
 
                 match &**define_left {
                     Node::BinaryExpr {
@@ -317,42 +434,44 @@ impl Block {
                     } => {
                         let comp_left_reg =
                             self.generate_node(&comp_left, &mut scopes, push_block)?;
-                        let comp_right_reg = if let Node::Ident(name) = &**comp_right {
-                            let right_as_str = Node::StringLiteral(name.clone());
+                        let comp_right_reg = if let Node::Ident(name, span, _) = &**comp_right {
+                            let right_as_str = Node::StringLiteral(name.clone(), *span);
                             self.generate_node(&right_as_str, &mut scopes, push_block)?
                         } else {
                             self.generate_node(&comp_right, &mut scopes, push_block)?
                         };
 
-                        let dest = self.iota();
-                        self.code.push(Inst {
-                            dest,
+                        self.emit(Inst {
+                            dest: comp_left_reg,
                             op: Op::SetComp(comp_left_reg, comp_right_reg, right_reg),
                         });
                         comp_left_reg
                     }
-                    Node::Ident(name) => match scopes.get(name) {
-                        Some(rec) => {
-                            self.code.push(Inst {
-                                dest: rec.reg,
-                                op: Op::Mov(right_reg),
+                    Node::Ident(name, _, resolved) => {
+                        // := always defines a name in the current scope. Names are
+                        // forward-declared at the top of their scope, except when the
+                        // definition is nested in another expression, like `f(x := 1)`,
+                        // which moves to the heap once it's defined if it escapes.
+                        let (reg, escapes) = match scopes.local(name) {
+                            Some(reg) => (reg, false),
+                            None => (self.iota(), *resolved == Resolved::Local { escapes: true }),
+                        };
+                        self.emit(Inst {
+                            dest: reg,
+                            op: Op::Mov(right_reg),
+                        });
+                        scopes.insert(name.clone(), reg);
+                        if escapes {
+                            self.emit(Inst {
+                                dest: reg,
+                                op: Op::Escape(reg),
                             });
-                            scopes.insert(name.clone(), rec.reg);
-                            rec.reg
-                        }
-                        // We expect all name bindings to be forward-declared
-                        // at the top of this scope's codegen.
-                        None => {
-                            println!(
-                                "Could not find forward-declared \"{:?}\" in current scope",
-                                name
-                            );
-                            return Err(InkErr::UndefinedVariable);
                         }
-                    },
+                        reg
+                    }
                     Node::EmptyIdent => right_reg,
                     _ => {
-                        println!("Invalid assignment expression: {:?}", node);
+                        eprintln!("Invalid assignment expression: {:?}", node);
                         return Err(InkErr::InvalidAssignment);
                     }
                 }
@@ -363,14 +482,14 @@ impl Block {
                 right: access_right,
             } => {
                 let left_reg = self.generate_node(&access_left, &mut scopes, push_block)?;
-                let right_reg = if let Node::Ident(name) = &**access_right {
-                    let right_as_str = Node::StringLiteral(name.clone());
+                let right_reg = if let Node::Ident(name, span, _) = &**access_right {
+                    let right_as_str = Node::StringLiteral(name.clone(), *span);
                     self.generate_node(&right_as_str, &mut scopes, push_block)?
                 } else {
                     self.generate_node(&access_right, &mut scopes, push_block)?
                 };
                 let dest = self.iota();
-                self.code.push(Inst {
+                self.emit(Inst {
                     dest,
                     op: Op::GetComp(left_reg, right_reg),
                 });
@@ -381,52 +500,52 @@ impl Block {
                 let right_reg = self.generate_node(&right, &mut scopes, push_block)?;
                 let dest = self.iota();
                 match op {
-                    TokKind::AddOp => self.code.push(Inst {
+                    TokKind::AddOp => self.emit(Inst {
                         dest,
                         op: Op::Add(left_reg, right_reg),
                     }),
-                    TokKind::SubOp => self.code.push(Inst {
+                    TokKind::SubOp => self.emit(Inst {
                         dest,
                         op: Op::Sub(left_reg, right_reg),
                     }),
-                    TokKind::MulOp => self.code.push(Inst {
+                    TokKind::MulOp => self.emit(Inst {
                         dest,
                         op: Op::Mul(left_reg, right_reg),
                     }),
-                    TokKind::DivOp => self.code.push(Inst {
+                    TokKind::DivOp => self.emit(Inst {
                         dest,
                         op: Op::Div(left_reg, right_reg),
                     }),
-                    TokKind::ModOp => self.code.push(Inst {
+                    TokKind::ModOp => self.emit(Inst {
                         dest,
                         op: Op::Mod(left_reg, right_reg),
                     }),
-                    TokKind::GtOp => self.code.push(Inst {
+                    TokKind::GtOp => self.emit(Inst {
                         dest,
                         op: Op::Gtr(left_reg, right_reg),
                     }),
-                    TokKind::LtOp => self.code.push(Inst {
+                    TokKind::LtOp => self.emit(Inst {
                         dest,
                         op: Op::Lss(left_reg, right_reg),
                     }),
-                    TokKind::EqOp => self.code.push(Inst {
+                    TokKind::EqOp => self.emit(Inst {
                         dest,
                         op: Op::Eql(left_reg, right_reg),
                     }),
-                    TokKind::AndOp => self.code.push(Inst {
+                    TokKind::AndOp => self.emit(Inst {
                         dest,
                         op: Op::And(left_reg, right_reg),
                     }),
-                    TokKind::OrOp => self.code.push(Inst {
+                    TokKind::OrOp => self.emit(Inst {
                         dest,
                         op: Op::Or(left_reg, right_reg),
                     }),
-                    TokKind::XorOp => self.code.push(Inst {
+                    TokKind::XorOp => self.emit(Inst {
                         dest,
                         op: Op::Xor(left_reg, right_reg),
                     }),
                     _ => {
-                        println!("Cannot compile binary op {:?}", op);
+                        eprintln!("Cannot compile binary op {:?}", op);
                         return Err(InkErr::Unimplemented);
                     }
                 }
@@ -439,18 +558,24 @@ impl Block {
                     arg_regs.push(self.generate_node(arg, &mut scopes, push_block)?);
                 }
                 let dest = self.iota();
-                self.code.push(Inst {
+                self.emit(Inst {
                     dest,
                     op: Op::Call(func_reg, arg_regs),
                 });
                 dest
             }
             Node::MatchClause { target: _, expr: _ } => {
-                panic!("Unexpected node in compiler: Node::MatchClause")
+                return Err(InkErr::UnexpectedNode("MatchClause".to_string()));
             }
             Node::MatchExpr { cond, clauses } => {
                 let cond_reg = self.generate_node(cond, &mut scopes, push_block)?;
                 let dest = self.iota();
+                // a match with no matching clause evaluates to null
+                let null_const = self.push_const(Val::Null);
+                self.emit(Inst {
+                    dest,
+                    op: Op::LoadConst(null_const),
+                });
                 for (i, clause) in clauses.iter().enumerate() {
                     match clause {
                         Node::MatchClause { target, expr } => {
@@ -462,7 +587,7 @@ impl Block {
                             };
                             let expr_reg =
                                 self.generate_node(&exprlist, &mut scopes, push_block)?;
-                            self.code.push(Inst {
+                            self.emit(Inst {
                                 dest,
                                 op: Op::CallIfEq(
                                     expr_reg,
@@ -472,7 +597,11 @@ impl Block {
                                 ),
                             });
                         }
-                        _ => panic!("Unexpected node in compiler: non-MatchClause in MatchExpr"),
+                        _ => {
+                            return Err(InkErr::UnexpectedNode(
+                                "non-MatchClause in MatchExpr".to_string(),
+                            ))
+                        }
                     }
                 }
                 dest
@@ -481,7 +610,7 @@ impl Block {
                 if exprs.len() == 0 {
                     let dest = self.iota();
                     let const_dest = self.push_const(Val::Null);
-                    self.code.push(Inst {
+                    self.emit(Inst {
                         dest,
                         op: Op::LoadConst(const_dest),
                     });
@@ -490,50 +619,18 @@ impl Block {
                     scopes.push();
                     let mut exprlist_block =
                         Block::from_nodes(exprs.clone(), &mut scopes, push_block)?;
-                    scopes.pop();
-
-                    let mut pass_thru_names = Vec::<String>::new();
-                    for (name, rec) in scopes.last() {
-                        if !rec.escaped {
-                            continue;
-                        }
-
-                        if rec.from_current_scope {
-                            self.code.push(Inst {
-                                dest: rec.reg,
-                                op: Op::Escape(rec.reg),
-                            });
-                        } else {
-                            pass_thru_names.push(name.clone());
-                        }
-                    }
-                    for name in pass_thru_names.iter() {
-                        let binds_idx = exprlist_block
-                            .binds_names
-                            .iter()
-                            .position(|nm| nm == name)
-                            .unwrap();
-
-                        // codegen for a fake `name := name`
-                        let right = Node::Ident(name.to_string());
-                        let right_reg = self.generate_node(&right, &mut scopes, push_block)?;
-
-                        // update the callee's last bind to point to the caller's correct register for
-                        // the pass-thru bind variable.
-                        scopes.insert(name.clone(), right_reg);
-                        let last_bind = exprlist_block.binds.get_mut(binds_idx).unwrap();
-                        *last_bind = right_reg;
-                    }
+                    let exprlist_scope = scopes.pop();
+                    self.bind(&mut exprlist_block, exprlist_scope, &mut scopes)?;
                     let block_idx = push_block(exprlist_block);
 
                     let closure_dest = self.iota();
                     let const_dest = self.push_const(Val::Func(block_idx, vec![]));
-                    self.code.push(Inst {
+                    self.emit(Inst {
                         dest: closure_dest,
                         op: Op::LoadConst(const_dest),
                     });
                     let call_dest = self.iota();
-                    self.code.push(Inst {
+                    self.emit(Inst {
                         dest: call_dest,
                         op: Op::Call(closure_dest, Vec::new()),
                     });
@@ -542,71 +639,79 @@ impl Block {
             }
             Node::EmptyIdent => {
                 let dest = self.iota();
-                self.code.push(Inst { dest, op: Op::Nop });
+                self.emit(Inst { dest, op: Op::Nop });
                 dest
             }
-            Node::Ident(name) => match scopes.get(name) {
-                Some(lookup) => {
-                    if lookup.from_current_scope {
-                        self.code.push(Inst {
-                            dest: lookup.reg,
-                            op: Op::Nop,
-                        });
-                        lookup.reg
-                    } else {
-                        let bind_idx = self.binds.len();
-                        self.binds_names.push(name.clone());
-                        self.binds.push(lookup.reg);
-
-                        let dest = self.iota();
-                        self.code.push(Inst {
-                            dest,
-                            op: Op::LoadEsc(bind_idx),
-                        });
-                        // There is now a local Val::Escaped pointing to the heap, so future
-                        // variable accesses in this scope should not LOAD_ESC
-                        scopes.insert(name.clone(), dest);
-                        dest
+            Node::Ident(name, _, resolved) => {
+                let depth = match resolved {
+                    Resolved::Local { escapes: _ } => 0,
+                    Resolved::Captured(depth) => *depth,
+                    // names the program is compiled with are defined in the
+                    // main scope, before the program
+                    Resolved::Builtin => scopes.depth(),
+                    Resolved::Undefined => {
+                        eprintln!("Could not find \"{}\" in current scope", name);
+                        return Err(InkErr::UndefinedVariable);
                     }
+                    Resolved::Unresolved => {
+                        return Err(InkErr::UnexpectedNode(format!("unresolved name {}", name)));
+                    }
+                };
+
+                if depth == 0 {
+                    let reg = match resolved {
+                        Resolved::Builtin => scopes.predefined(name),
+                        _ => scopes.local(name),
+                    };
+                    let reg = match reg {
+                        Some(reg) => reg,
+                        None => {
+                            eprintln!("Could not find \"{}\" in current scope", name);
+                            return Err(InkErr::UndefinedVariable);
+                        }
+                    };
+                    self.emit(Inst {
+                        dest: reg,
+                        op: Op::Nop,
+                    });
+                    reg
+                } else {
+                    self.load_captured(name, depth, scopes)
                 }
-                None => {
-                    println!("Could not find \"{}\" in current scope", name);
-                    return Err(InkErr::UndefinedVariable);
-                }
-            },
-            Node::NumberLiteral(n) => {
+            }
+            Node::NumberLiteral(n, _) => {
                 let dest = self.iota();
                 let const_dest = self.push_const(Val::Number(n.clone()));
-                self.code.push(Inst {
+                self.emit(Inst {
                     dest,
                     op: Op::LoadConst(const_dest),
                 });
                 dest
             }
-            Node::StringLiteral(s) => {
+            Node::StringLiteral(s, _) => {
                 let dest = self.iota();
-                let const_dest = self.push_const(Val::Str(s.clone().into_bytes()));
-                self.code.push(Inst {
+                let const_dest = self.push_const(Val::Str(lex::unescape(s)?));
+                self.emit(Inst {
                     dest,
                     op: Op::LoadConst(const_dest),
                 });
                 dest
             }
-            Node::BooleanLiteral(b) => {
+            Node::BooleanLiteral(b, _) => {
                 let dest = self.iota();
                 let const_dest = self.push_const(Val::Bool(b.clone()));
-                self.code.push(Inst {
+                self.emit(Inst {
                     dest,
                     op: Op::LoadConst(const_dest),
                 });
                 dest
             }
             Node::ObjectEntry { key: _, val: _ } => {
-                panic!("Unexpected node in compiler: Node::ObjectEntry")
+                return Err(InkErr::UnexpectedNode("ObjectEntry".to_string()));
             }
             Node::ObjectLiteral(entries) => {
                 let dest = self.iota();
-                self.code.push(Inst {
+                self.emit(Inst {
                     dest,
                     op: Op::MakeComp,
                 });
@@ -614,150 +719,227 @@ impl Block {
                     match entry {
                         Node::ObjectEntry { key, val } => {
                             let key_reg: Reg;
-                            if let Node::Ident(key_name) = &**key {
-                                let key_node = Node::StringLiteral(key_name.clone());
+                            if let Node::Ident(key_name, span, _) = &**key {
+                                let key_node = Node::StringLiteral(key_name.clone(), *span);
                                 key_reg = self.generate_node(&key_node, &mut scopes, push_block)?;
                             } else {
                                 key_reg = self.generate_node(key, &mut scopes, push_block)?;
                             }
                             let val_reg = self.generate_node(val, &mut scopes, push_block)?;
-                            let entry_dest = self.iota();
-                            self.code.push(Inst {
-                                dest: entry_dest,
+                            // SET_COMP does not write to its dest, and pointing it at the
+                            // composite makes the composite the block's return value if
+                            // this is the block's last expression.
+                            self.emit(Inst {
+                                dest,
                                 op: Op::SetComp(dest, key_reg, val_reg),
                             });
                         }
-                        _ => panic!("unreachable!"),
+                        _ => {
+                            return Err(InkErr::UnexpectedNode(
+                                "non-ObjectEntry in ObjectLiteral".to_string(),
+                            ))
+                        }
                     }
                 }
                 dest
             }
             Node::ListLiteral(items) => {
                 let dest = self.iota();
-                self.code.push(Inst {
+                self.emit(Inst {
                     dest,
                     op: Op::MakeComp,
                 });
                 for (i, item) in items.iter().enumerate() {
                     let index_dest = self.iota();
                     let index_reg = self.push_const(Val::Number(i as f64));
-                    self.code.push(Inst {
+                    self.emit(Inst {
                         dest: index_dest,
                         op: Op::LoadConst(index_reg),
                     });
 
                     let item_reg = self.generate_node(item, &mut scopes, push_block)?;
-                    let item_dest = self.iota();
-                    self.code.push(Inst {
-                        dest: item_dest,
+                    self.emit(Inst {
+                        dest,
                         op: Op::SetComp(dest, index_dest, item_reg),
                     });
                 }
                 dest
             }
             Node::FnLiteral { args, body } => {
-                scopes.push();
-                let mut func_block = Block::new();
-                for arg in args.iter() {
-                    match arg {
-                        Node::Ident(name) => {
-                            let arg_reg = func_block.iota();
-                            scopes.insert(name.clone(), arg_reg);
-                        }
-                        _ => (),
-                    }
-                }
-                match &**body {
-                    Node::ExprList(exprs) => {
-                        if exprs.len() == 0 {
-                            // special case for _ => () which should be generated as
-                            // _ => (()) (null value expression list), because we don't have an AST
-                            // representation of the null () constant.
-                            func_block.generate_nodes(
-                                vec![Node::ExprList(vec![])],
-                                &mut scopes,
-                                push_block,
-                            )?
-                        } else {
-                            func_block.generate_nodes(exprs.to_vec(), &mut scopes, push_block)?
-                        }
-                    }
-                    _ => func_block.generate_nodes(vec![*body.clone()], &mut scopes, push_block)?,
-                }
-                scopes.pop();
+                self.generate_fn(args, body, "", &mut scopes, push_block)?
+            }
+            // comments are only kept for the formatter
+            Node::Trivia { .. } => {
+                return Err(InkErr::UnexpectedNode("Trivia".to_string()));
+            }
+        };
 
-                let mut pass_thru_names = Vec::<String>::new();
-                for (name, rec) in scopes.last() {
-                    if !rec.escaped {
-                        continue;
-                    }
+        return Ok(result_reg);
+    }
 
-                    if rec.from_current_scope {
-                        self.code.push(Inst {
-                            dest: rec.reg,
-                            op: Op::Escape(rec.reg),
+    // generate_fn compiles a function literal into a new block, and returns the
+    // register holding the function value.
+    fn generate_fn<F>(
+        &mut self,
+        args: &[Node],
+        body: &Node,
+        name: &str,
+        mut scopes: &mut ScopeStack,
+        push_block: &mut F,
+    ) -> Result<Reg, InkErr>
+    where
+        F: FnMut(Block) -> usize,
+    {
+        scopes.push();
+        let mut func_block = Block::new();
+        func_block.name = name.to_string();
+        for arg in args.iter() {
+            match arg {
+                Node::Ident(name, _, resolved) => {
+                    let arg_reg = func_block.iota();
+                    scopes.insert(name.clone(), arg_reg);
+                    if let Resolved::Local { escapes: true } = resolved {
+                        func_block.emit(Inst {
+                            dest: arg_reg,
+                            op: Op::Escape(arg_reg),
                         });
-                    } else {
-                        pass_thru_names.push(name.clone());
                     }
                 }
-                for name in pass_thru_names.iter() {
-                    let binds_idx = func_block
-                        .binds_names
-                        .iter()
-                        .position(|nm| nm == name)
-                        .unwrap();
-
-                    // codegen for a fake `name := name`
-                    let right = Node::Ident(name.to_string());
-                    let right_reg = self.generate_node(&right, &mut scopes, push_block)?;
-
-                    // update the callee's last bind to point to the caller's correct register for
-                    // the pass-thru bind variable.
-                    scopes.insert(name.clone(), right_reg);
-                    let last_bind = func_block.binds.get_mut(binds_idx).unwrap();
-                    *last_bind = right_reg;
+                _ => (),
+            }
+        }
+        match body {
+            Node::ExprList(exprs) => {
+                if exprs.len() == 0 {
+                    // special case for _ => () which should be generated as
+                    // _ => (()) (null value expression list), because we don't have an AST
+                    // representation of the null () constant.
+                    func_block.generate_nodes(
+                        vec![Node::ExprList(vec![])],
+                        &mut scopes,
+                        push_block,
+                    )?
+                } else {
+                    func_block.generate_nodes(exprs.to_vec(), &mut scopes, push_block)?
                 }
-                let block_idx = push_block(func_block);
-
-                let fn_dest = self.iota();
-                let const_dest = self.push_const(Val::Func(block_idx, vec![]));
-                self.code.push(Inst {
-                    dest: fn_dest,
-                    op: Op::LoadConst(const_dest),
-                });
-                fn_dest
             }
-        };
+            _ => func_block.generate_nodes(vec![body.clone()], &mut scopes, push_block)?,
+        }
+        let func_scope = scopes.pop();
+        self.bind(&mut func_block, func_scope, &mut scopes)?;
+        let block_idx = push_block(func_block);
+
+        let fn_dest = self.iota();
+        let const_dest = self.push_const(Val::Func(block_idx, vec![]));
+        self.emit(Inst {
+            dest: fn_dest,
+            op: Op::LoadConst(const_dest),
+        });
+        return Ok(fn_dest);
+    }
 
-        return Ok(result_reg);
+    // load_captured loads a name defined the given number of scopes out into
+    // a register, from the block's binds. Each name is loaded once per block,
+    // where the block first uses it.
+    fn load_captured(&mut self, name: &str, depth: usize, scopes: &mut ScopeStack) -> Reg {
+        if let Some(reg) = scopes.captured(name) {
+            return reg;
+        }
+
+        let bind_idx = self.binds.len();
+        self.binds_names.push(name.to_string());
+        // the parent points the bind at its register once this block is
+        // generated
+        self.binds.push(0);
+        let dest = self.iota();
+        self.emit(Inst {
+            dest,
+            op: Op::LoadEsc(bind_idx),
+        });
+        scopes.capture(name.to_string(), depth, dest);
+        return dest;
+    }
+
+    // bind points each bind of a child block at the register in this block
+    // that holds the name it captures. Names defined in this scope are
+    // already on the heap, except those defined before the program, which
+    // move there the first time they're captured. Names defined further out
+    // are captured by this block in turn.
+    fn bind(
+        &mut self,
+        child: &mut Block,
+        child_scope: Scope,
+        scopes: &mut ScopeStack,
+    ) -> Result<(), InkErr> {
+        for (bind_idx, (name, depth, _)) in child_scope.captures.into_iter().enumerate() {
+            let reg = if depth > 1 {
+                self.load_captured(&name, depth - 1, scopes)
+            } else {
+                let reg = match scopes.local(&name) {
+                    Some(reg) => reg,
+                    None => {
+                        eprintln!("Could not find \"{}\" in current scope", name);
+                        return Err(InkErr::UndefinedVariable);
+                    }
+                };
+                if scopes.scopes.last_mut().unwrap().unescaped.remove(&name) {
+                    self.emit(Inst {
+                        dest: reg,
+                        op: Op::Escape(reg),
+                    });
+                }
+                reg
+            };
+            child.binds[bind_idx] = reg;
+        }
+        return Ok(());
     }
 }
 
-pub fn generate(nodes: Vec<Node>) -> Result<Vec<Block>, InkErr> {
-    let mut prog = Vec::<Block>::new();
+pub fn generate(nodes: Vec<Node>, builtins: Vec<NativeFn>) -> Result<Vec<Block>, InkErr> {
     let mut main_scopes = ScopeStack::new();
-    let mut main_block = Block::new();
-
-    // initialize runtime preamble
-    let mut builtins: HashMap<String, NativeFn> = HashMap::new();
-    builtins.insert("out".to_string(), runtime::builtin_out);
-    builtins.insert("char".to_string(), runtime::builtin_char);
-    builtins.insert("string".to_string(), runtime::builtin_string);
-    builtins.insert("len".to_string(), runtime::builtin_len);
-    builtins.insert("load".to_string(), runtime::builtin_load);
+    let main_block = preamble(builtins, &mut main_scopes);
+    return generate_main(nodes, main_block, &mut main_scopes);
+}
 
-    for (name, builtin_fn) in builtins {
+// preamble starts a main block that loads the runtime's builtins
+fn preamble(builtins: Vec<NativeFn>, main_scopes: &mut ScopeStack) -> Block {
+    let mut main_block = Block::new();
+    for builtin_fn in builtins {
+        let name = builtin_fn.name.clone();
         let builtin_idx = main_block.push_const(Val::NativeFunc(builtin_fn));
         let builtin_reg = main_block.iota();
-        main_block.code.push(Inst {
+        main_block.emit(Inst {
             dest: builtin_reg,
             op: Op::LoadConst(builtin_idx),
         });
-        main_scopes.insert(name, builtin_reg);
+        main_scopes.predefine(name, builtin_reg);
     }
+    return main_block;
+}
+
+// generate_with_params compiles a program whose main block takes the given
+// names as arguments, like the body of a function. The debugger uses this to
+// evaluate expressions against variables in a paused program, which are passed
+// in when the main block is called.
+pub fn generate_with_params(nodes: Vec<Node>, params: Vec<String>) -> Result<Vec<Block>, InkErr> {
+    let mut main_scopes = ScopeStack::new();
+    let mut main_block = Block::new();
+    for name in params {
+        let param_reg = main_block.iota();
+        main_scopes.predefine(name, param_reg);
+    }
+
+    return generate_main(nodes, main_block, &mut main_scopes);
+}
 
-    main_block.generate_nodes(nodes, &mut main_scopes, &mut |block| {
+fn generate_main(
+    nodes: Vec<Node>,
+    mut main_block: Block,
+    main_scopes: &mut ScopeStack,
+) -> Result<Vec<Block>, InkErr> {
+    let mut prog = Vec::<Block>::new();
+    main_block.generate_nodes(nodes, main_scopes, &mut |block| {
         prog.push(block);
         return prog.len();
     })?;
@@ -0,0 +1,166 @@
+// Golden file tests, behind `schrift test`. A test is an Ink program with a
+// `.expected` file next to it, like `test/007.ink` and `test/007.expected`,
+// that holds everything the program is expected to print.
+//
+// Each test is compiled and run in its own VM, with an out() builtin that
+// collects output instead of printing it. If the program fails to compile or
+// run, the error is appended to its output, so golden files can cover errors
+// too. A program that crashes the compiler or VM fails without taking the
+// other tests down.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::debug;
+use crate::err::InkErr;
+use crate::runtime;
+use crate::val::{NativeFn, Val};
+use crate::vm::Vm;
+
+const EXPECTED_EXT: &str = "expected";
+
+pub enum Outcome {
+    Pass,
+    // the program's output differed from its golden file, with a diff
+    Fail(String),
+    // the program has no golden file
+    Missing,
+    // the golden file was rewritten with the program's output
+    Updated,
+}
+
+pub fn expected_path(path: &Path) -> PathBuf {
+    return path.with_extension(EXPECTED_EXT);
+}
+
+// discover returns the tests under a directory, recursively, in a stable
+// order. A path to an Ink program is a test by itself, whether or not it has
+// a golden file yet.
+pub fn discover(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    let mut tests = Vec::<PathBuf>::new();
+    for entry in entries {
+        if entry.is_dir() {
+            tests.append(&mut discover(&entry)?);
+        } else if entry.extension().is_some_and(|ext| ext == "ink")
+            && expected_path(&entry).is_file()
+        {
+            tests.push(entry);
+        }
+    }
+    return Ok(tests);
+}
+
+// run runs an Ink program and returns everything it printed
+pub fn run(source: &str) -> Vec<u8> {
+    let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let out_buf = output.clone();
+    let out = NativeFn::new("out", move |_ctx, args: Vec<Val>| {
+        return match args.first() {
+            Some(Val::Str(s)) => {
+                out_buf.borrow_mut().extend_from_slice(s);
+                Ok(args[0].clone())
+            }
+            Some(_) => Err(InkErr::InvalidArguments),
+            None => Err(InkErr::NotEnoughArguments),
+        };
+    });
+
+    let mut builtins = runtime::builtins();
+    for builtin in builtins.iter_mut() {
+        if builtin.name == "out" {
+            *builtin = out.clone();
+        }
+    }
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let blocks = debug::compile(source, builtins)?;
+        return Vm::new(blocks).run();
+    }));
+    let mut output = output.take();
+    match result {
+        Ok(Ok(_)) => (),
+        Ok(Err(err)) => output.extend_from_slice(format!("error: {:?}\n", err).as_bytes()),
+        Err(_) => output.extend_from_slice(b"error: crashed\n"),
+    }
+    return output;
+}
+
+// check runs a test and compares its output against its golden file, or
+// rewrites the golden file if `update` is set
+pub fn check(path: &Path, update: bool) -> io::Result<Outcome> {
+    let source = fs::read_to_string(path)?;
+    let actual = run(&source);
+
+    let expected_path = expected_path(path);
+    if update {
+        fs::write(expected_path, actual)?;
+        return Ok(Outcome::Updated);
+    }
+
+    let expected = match fs::read(expected_path) {
+        Ok(expected) => expected,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Outcome::Missing),
+        Err(e) => return Err(e),
+    };
+    if expected == actual {
+        return Ok(Outcome::Pass);
+    }
+    return Ok(Outcome::Fail(diff(
+        &String::from_utf8_lossy(&expected),
+        &String::from_utf8_lossy(&actual),
+    )));
+}
+
+// diff returns a line diff from `expected` to `actual`, with removed lines
+// marked by "-", added lines by "+", and unchanged lines indented.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let a: Vec<&str> = expected.lines().collect();
+    let b: Vec<&str> = actual.lines().collect();
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push_str(&format!("  {}\n", a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("- {}\n", a[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+ {}\n", b[j]));
+            j += 1;
+        }
+    }
+
+    // outputs that differ only in a trailing newline have the same lines
+    if out.lines().all(|line| line.starts_with("  ")) {
+        out.push_str("(outputs differ in line endings)\n");
+    }
+    return out;
+}
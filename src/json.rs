@@ -0,0 +1,238 @@
+// Native JSON serialization and parsing, backing the jsonSerialize and
+// jsonParse builtins. Output follows the conventions of the json.ink
+// standard library: compact, with only tab, newline, carriage return, quote
+// and backslash escaped in strings, and functions serialized as null. JSON
+// has no NaN or infinities, so non-finite numbers are serialized as null too.
+//
+// Composites whose keys are exactly the integers 0..len are serialized as
+// JSON arrays, and all other composites as objects with sorted keys.
+
+use crate::comp::Comp;
+use crate::val::Val;
+
+pub fn serialize(val: &Val) -> Vec<u8> {
+    let mut out = Vec::<u8>::new();
+    serialize_into(val, &mut out);
+    return out;
+}
+
+fn serialize_into(val: &Val, out: &mut Vec<u8>) {
+    match val {
+        Val::Null | Val::Empty => out.extend_from_slice(b"null"),
+        Val::Str(s) => serialize_str(s, out),
+        Val::Number(n) if !n.is_finite() => out.extend_from_slice(b"null"),
+        Val::Number(_) | Val::Bool(_) => out.extend_from_slice(val.to_ink_string().as_bytes()),
+        Val::Func(_, _) | Val::NativeFunc(_) | Val::Escaped(_) => out.extend_from_slice(b"null"),
+        Val::Comp(comp_rc) => {
+            let comp = comp_rc.borrow();
+            match comp.as_list() {
+                Some(items) if !items.is_empty() => {
+                    out.push(b'[');
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            out.push(b',');
+                        }
+                        serialize_into(item, out);
+                    }
+                    out.push(b']');
+                }
+                _ => {
+                    let mut keys: Vec<&String> = comp.map.keys().collect();
+                    keys.sort();
+
+                    out.push(b'{');
+                    for (i, key) in keys.iter().enumerate() {
+                        if i > 0 {
+                            out.push(b',');
+                        }
+                        serialize_str(key.as_bytes(), out);
+                        out.push(b':');
+                        serialize_into(&comp.map[*key], out);
+                    }
+                    out.push(b'}');
+                }
+            }
+        }
+    }
+}
+
+fn serialize_str(s: &[u8], out: &mut Vec<u8>) {
+    out.push(b'"');
+    for c in s {
+        match c {
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            _ => out.push(*c),
+        }
+    }
+    out.push(b'"');
+}
+
+// parse returns None if the input is not valid JSON. Like json.ink, trailing
+// whitespace is allowed but trailing data is not.
+pub fn parse(s: &[u8]) -> Option<Val> {
+    let mut parser = Parser { s, idx: 0 };
+    let val = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.idx < s.len() {
+        return None;
+    }
+    return Some(val);
+}
+
+struct Parser<'s> {
+    s: &'s [u8],
+    idx: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn peek(&self) -> Option<u8> {
+        return self.s.get(self.idx).copied();
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.idx += 1;
+        return Some(c);
+    }
+
+    fn expect(&mut self, c: u8) -> Option<()> {
+        if self.next()? == c {
+            return Some(());
+        }
+        return None;
+    }
+
+    fn expect_word(&mut self, word: &[u8]) -> Option<()> {
+        if self.s[self.idx..].starts_with(word) {
+            self.idx += word.len();
+            return Some(());
+        }
+        return None;
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.idx += 1;
+        }
+    }
+
+    fn parse_value(&mut self) -> Option<Val> {
+        self.skip_whitespace();
+        return match self.peek()? {
+            b'n' => self.expect_word(b"null").map(|_| Val::Null),
+            b't' => self.expect_word(b"true").map(|_| Val::Bool(true)),
+            b'f' => self.expect_word(b"false").map(|_| Val::Bool(false)),
+            b'"' => self.parse_str().map(Val::Str),
+            b'[' => self.parse_list(),
+            b'{' => self.parse_object(),
+            b'-' | b'0'..=b'9' => self.parse_number(),
+            _ => None,
+        };
+    }
+
+    fn parse_number(&mut self) -> Option<Val> {
+        let start = self.idx;
+        if self.peek() == Some(b'-') {
+            self.idx += 1;
+        }
+        while let Some(b'0'..=b'9') | Some(b'.') | Some(b'e') | Some(b'E') | Some(b'+')
+        | Some(b'-') = self.peek()
+        {
+            self.idx += 1;
+        }
+
+        let numeral = std::str::from_utf8(&self.s[start..self.idx]).ok()?;
+        return numeral.parse::<f64>().ok().map(Val::Number);
+    }
+
+    fn parse_hex4(&mut self) -> Option<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = (self.next()? as char).to_digit(16)?;
+            code = code * 16 + digit;
+        }
+        return Some(code);
+    }
+
+    fn parse_str(&mut self) -> Option<Vec<u8>> {
+        self.expect(b'"')?;
+
+        let mut s = Vec::<u8>::new();
+        loop {
+            match self.next()? {
+                b'"' => return Some(s),
+                b'\\' => match self.next()? {
+                    b't' => s.push(b'\t'),
+                    b'n' => s.push(b'\n'),
+                    b'r' => s.push(b'\r'),
+                    b'f' => s.push(0x0c),
+                    b'b' => s.push(0x08),
+                    b'u' => {
+                        let mut code = self.parse_hex4()?;
+                        // UTF-16 surrogate pairs encode a single code point
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect_word(b"\\u")?;
+                            let low = self.parse_hex4()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.checked_sub(0xdc00)?);
+                        }
+                        let c = std::char::from_u32(code)?;
+                        let mut buf = [0; 4];
+                        s.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    }
+                    c => s.push(c),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn parse_list(&mut self) -> Option<Val> {
+        self.expect(b'[')?;
+
+        let mut items = Vec::<Val>::new();
+        self.skip_whitespace();
+        if self.peek()? == b']' {
+            self.idx += 1;
+            return Some(Val::from(Comp::new()));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.next()? {
+                b',' => (),
+                b']' => return Some(Val::from(Comp::from_list(items))),
+                _ => return None,
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Option<Val> {
+        self.expect(b'{')?;
+
+        let mut comp = Comp::new();
+        self.skip_whitespace();
+        if self.peek()? == b'}' {
+            self.idx += 1;
+            return Some(Val::from(comp));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_str()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            let val = self.parse_value()?;
+            comp.set(&Val::Str(key), val);
+
+            self.skip_whitespace();
+            match self.next()? {
+                b',' => (),
+                b'}' => return Some(Val::from(comp)),
+                _ => return None,
+            }
+        }
+    }
+}
@@ -12,6 +12,7 @@ pub enum TokKind {
     EmptyIdent,
 
     NumberLiteral(f64),
+    // string literals hold their source text, with escapes as written
     StringLiteral(String),
 
     TrueLiteral,
@@ -49,57 +50,67 @@ pub enum TokKind {
     RBrace,
 }
 
-#[derive(Debug, Clone)]
-pub struct Span(usize, usize);
-
-#[derive(Debug, Clone)]
-pub struct Tok<'s> {
-    pub kind: TokKind,
-    pub span: Span,
-    source: &'s str,
-}
-
-impl fmt::Display for Tok<'_> {
+// tokens display as they are written in the source, for error messages
+impl fmt::Display for TokKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let pos = self.position();
-        write!(f, "{:?} [{}:{}]", self.kind, pos.line, pos.col,)
+        match self {
+            TokKind::Separator => write!(f, "separator"),
+            TokKind::Comment(_) => write!(f, "comment"),
+            TokKind::Ident(s) => write!(f, "'{}'", s),
+            TokKind::EmptyIdent => write!(f, "'_'"),
+            TokKind::NumberLiteral(n) => write!(f, "'{}'", n),
+            TokKind::StringLiteral(s) => write!(f, "string '{}'", s),
+            TokKind::TrueLiteral => write!(f, "'true'"),
+            TokKind::FalseLiteral => write!(f, "'false'"),
+            TokKind::AccessorOp => write!(f, "'.'"),
+            TokKind::EqOp => write!(f, "'='"),
+            TokKind::FunctionArrow => write!(f, "'=>'"),
+            TokKind::KeyValueSeparator => write!(f, "':'"),
+            TokKind::DefineOp => write!(f, "':='"),
+            TokKind::MatchColon => write!(f, "'::'"),
+            TokKind::CaseArrow => write!(f, "'->'"),
+            TokKind::SubOp => write!(f, "'-'"),
+            TokKind::NegOp => write!(f, "'~'"),
+            TokKind::AddOp => write!(f, "'+'"),
+            TokKind::MulOp => write!(f, "'*'"),
+            TokKind::DivOp => write!(f, "'/'"),
+            TokKind::ModOp => write!(f, "'%'"),
+            TokKind::GtOp => write!(f, "'>'"),
+            TokKind::LtOp => write!(f, "'<'"),
+            TokKind::AndOp => write!(f, "'&'"),
+            TokKind::OrOp => write!(f, "'|'"),
+            TokKind::XorOp => write!(f, "'^'"),
+            TokKind::LParen => write!(f, "'('"),
+            TokKind::RParen => write!(f, "')'"),
+            TokKind::LBracket => write!(f, "'['"),
+            TokKind::RBracket => write!(f, "']'"),
+            TokKind::LBrace => write!(f, "'{{'"),
+            TokKind::RBrace => write!(f, "'}}'"),
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct Position {
-    line: usize,
-    col: usize,
-}
+// Span is the range of a token in the source, as [start, end) byte offsets
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span(pub usize, pub usize);
 
-impl<'s> Tok<'s> {
-    fn position(&self) -> Position {
-        // first get to right line
-        let mut line: usize = 1;
-        let mut col: usize = 1;
-        for c in self.source[0..self.span.0].chars() {
-            if c == '\n' {
-                line += 1;
-                col = 0;
-            }
-            col += 1;
-        }
-        // then count columns
-        return Position { line, col };
-    }
+#[derive(Debug, Clone)]
+pub struct Tok {
+    pub kind: TokKind,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct Reader<'s> {
     source: &'s str,
+    // byte offsets into the source of the start of the current token, and of
+    // the next character, so that reading a character takes constant time
     start: usize,
     index: usize,
 }
 
-// impl should be more efficient. In particular, peek()
-// should support seeking thru Unicode source text in constant time.
 impl<'s> Reader<'s> {
-    fn new(source: &str) -> Reader {
+    fn new(source: &'s str) -> Reader<'s> {
         return Reader {
             source,
             start: 0,
@@ -107,25 +118,21 @@ impl<'s> Reader<'s> {
         };
     }
 
-    fn peek(&self) -> char {
-        return self
-            .source
-            .chars()
-            .nth(self.index)
-            .expect("Reader index out of bounds in peek");
+    // peek returns the next character, or an error at the end of the source
+    fn peek(&self) -> Result<char, (InkErr, Span)> {
+        return match self.source[self.index..].chars().next() {
+            Some(c) => Ok(c),
+            None => Err((InkErr::UnexpectedEOF, Span(self.start, self.index))),
+        };
     }
 
-    fn lookback(&self) -> char {
-        return self
-            .source
-            .chars()
-            .nth(self.index - 1)
-            .expect("Reader index out of bounds in lookback");
+    fn lookback(&self) -> Option<char> {
+        return self.source[..self.index].chars().next_back();
     }
 
     fn next(&mut self) {
-        if self.has_next() {
-            self.index += 1;
+        if let Ok(c) = self.peek() {
+            self.index += c.len_utf8();
         }
     }
 
@@ -139,43 +146,101 @@ impl<'s> Reader<'s> {
         return span;
     }
 
-    fn pop_token(&mut self, kind: TokKind) -> Tok<'s> {
+    fn pop_token(&mut self, kind: TokKind) -> Tok {
         return Tok {
             kind,
             span: self.pop_span(),
-            source: self.source,
         };
     }
 
-    fn pop_token_and_next(&mut self, kind: TokKind) -> Tok<'s> {
+    fn pop_token_and_next(&mut self, kind: TokKind) -> Tok {
         self.next();
         return Tok {
             kind,
             span: self.pop_span(),
-            source: self.source,
         };
     }
 
-    fn take_while<F>(&mut self, cond: F) -> &str
+    fn take_while<F>(&mut self, cond: F) -> String
     where
         F: Fn(char) -> bool,
     {
-        while self.has_next() && (cond(self.peek()) || self.lookback() == '\\') {
+        while let Ok(c) = self.peek() {
+            if !cond(c) && self.lookback() != Some('\\') {
+                break;
+            }
             self.next();
         }
         return self.take();
     }
 
-    fn take(&self) -> &str {
-        return &self.source[self.start..self.index];
+    // take_string takes the text of a string literal up to its closing quote,
+    // skipping over escaped quotes
+    fn take_string(&mut self) -> String {
+        let mut escaped = false;
+        while let Ok(c) = self.peek() {
+            if c == '\'' && !escaped {
+                break;
+            }
+            escaped = c == '\\' && !escaped;
+            self.next();
+        }
+        return self.take();
+    }
+
+    fn take(&self) -> String {
+        return self.source[self.start..self.index].to_string();
     }
 }
 
+// unescape returns the bytes of a string literal from its source text. A
+// backslash followed by n, r, f or t is a newline, carriage return, form feed
+// or tab, \x followed by two hex digits is that byte, and a backslash followed
+// by any other character is that character, as in \' and \\.
+pub fn unescape(text: &str) -> Result<Vec<u8>, InkErr> {
+    let mut bytes = Vec::<u8>::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('f') => bytes.push(0x0c),
+            Some('t') => bytes.push(b'\t'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                if hex.len() != 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(InkErr::InvalidEscape(format!("\\x{}", hex)));
+                }
+                bytes.push(u8::from_str_radix(&hex, 16).unwrap());
+            }
+            Some(c) => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            // only an unterminated literal can end in a backslash
+            None => return Err(InkErr::InvalidEscape("\\".to_string())),
+        }
+    }
+    return Ok(bytes);
+}
+
 pub fn tokenize(prog: &str) -> Result<Vec<Tok>, InkErr> {
+    return tokenize_spanned(prog).map_err(|(err, _)| err);
+}
+
+// tokenize_spanned is tokenize, but on error also returns the span of the
+// source that could not be tokenized, for tools that point at errors.
+pub fn tokenize_spanned(prog: &str) -> Result<Vec<Tok>, (InkErr, Span)> {
     let mut tokens = Vec::<Tok>::new();
     let mut reader = Reader::new(prog);
 
-    fn ensure_separator<'s>(tokens: &mut Vec<Tok<'s>>, reader: &mut Reader<'s>) {
+    fn ensure_separator(tokens: &mut Vec<Tok>, reader: &mut Reader) {
         match tokens.last() {
             Some(tok) => match tok.kind {
                 TokKind::Separator
@@ -205,15 +270,17 @@ pub fn tokenize(prog: &str) -> Result<Vec<Tok>, InkErr> {
     }
 
     while reader.has_next() {
-        let c = reader.peek();
+        let c = reader.peek()?;
 
         match c {
             '\'' => {
                 reader.next(); // opening quote
                 reader.pop_span();
 
-                let str_content = reader.take_while(|c| c != '\'');
-                let str_value = String::from(str_content);
+                let str_value = reader.take_string();
+                if let Err(err) = unescape(&str_value) {
+                    return Err((err, reader.pop_span()));
+                }
                 tokens.push(reader.pop_token(TokKind::StringLiteral(str_value)));
 
                 reader.next(); // closing quote
@@ -221,15 +288,14 @@ pub fn tokenize(prog: &str) -> Result<Vec<Tok>, InkErr> {
             '`' => {
                 reader.next(); // opening backtick
 
-                if reader.peek() == '`' {
+                if reader.peek()? == '`' {
                     ensure_separator(&mut tokens, &mut reader);
 
                     // line comment
                     reader.next(); // second backtick
                     reader.pop_span();
 
-                    let str_content = reader.take_while(|c| c != '\n');
-                    let str_value = String::from(str_content);
+                    let str_value = reader.take_while(|c| c != '\n');
                     tokens.push(reader.pop_token(TokKind::Comment(str_value)));
 
                     reader.next(); // newline
@@ -238,8 +304,7 @@ pub fn tokenize(prog: &str) -> Result<Vec<Tok>, InkErr> {
                     // block comment
                     reader.pop_span();
 
-                    let str_content = reader.take_while(|c| c != '`');
-                    let str_value = String::from(str_content);
+                    let str_value = reader.take_while(|c| c != '`');
                     tokens.push(reader.pop_token(TokKind::Comment(str_value)));
 
                     reader.next(); // closing backtick
@@ -285,31 +350,31 @@ pub fn tokenize(prog: &str) -> Result<Vec<Tok>, InkErr> {
             ':' => {
                 reader.next();
                 match reader.peek() {
-                    ':' => {
+                    Ok(':') => {
                         tokens.push(reader.pop_token_and_next(TokKind::MatchColon));
                     }
-                    '=' => {
+                    Ok('=') => {
                         tokens.push(reader.pop_token_and_next(TokKind::DefineOp));
                     }
-                    _ => tokens.push(reader.pop_token_and_next(TokKind::KeyValueSeparator)),
+                    _ => tokens.push(reader.pop_token(TokKind::KeyValueSeparator)),
                 }
             }
             '=' => {
                 reader.next();
                 match reader.peek() {
-                    '>' => {
+                    Ok('>') => {
                         tokens.push(reader.pop_token_and_next(TokKind::FunctionArrow));
                     }
-                    _ => tokens.push(reader.pop_token_and_next(TokKind::EqOp)),
+                    _ => tokens.push(reader.pop_token(TokKind::EqOp)),
                 }
             }
             '-' => {
                 reader.next();
                 match reader.peek() {
-                    '>' => {
+                    Ok('>') => {
                         tokens.push(reader.pop_token_and_next(TokKind::CaseArrow));
                     }
-                    _ => tokens.push(reader.pop_token_and_next(TokKind::SubOp)),
+                    _ => tokens.push(reader.pop_token(TokKind::SubOp)),
                 }
             }
             '0'..='9' => {
@@ -317,19 +382,28 @@ pub fn tokenize(prog: &str) -> Result<Vec<Tok>, InkErr> {
                 let r = numeral.parse::<f64>();
                 match r {
                     Ok(num) => tokens.push(reader.pop_token(TokKind::NumberLiteral(num))),
-                    Err(_) => return Err(InkErr::InvalidNumber(String::from(numeral))),
+                    Err(_) => {
+                        let err = InkErr::InvalidNumber(numeral);
+                        return Err((err, reader.pop_span()));
+                    }
                 }
             }
             _ => {
-                // TODO support full unicode
-                let ident = reader
-                    .take_while(|c| c.is_ascii_alphanumeric() || c == '?' || c == '!' || c == '@');
+                // identifiers are letters and digits in any script, and ?, ! and @
+                let ident =
+                    reader.take_while(|c| c.is_alphanumeric() || c == '?' || c == '!' || c == '@');
+
+                // characters that can't start any token would otherwise never
+                // be consumed
+                if ident.is_empty() {
+                    reader.next();
+                    return Err((InkErr::UnexpectedCharacter(c), reader.pop_span()));
+                }
 
-                let ident_bit = String::from(ident);
-                match ident {
+                match &ident[..] {
                     "true" => tokens.push(reader.pop_token(TokKind::TrueLiteral)),
                     "false" => tokens.push(reader.pop_token(TokKind::FalseLiteral)),
-                    _ => tokens.push(reader.pop_token(TokKind::Ident(ident_bit))),
+                    _ => tokens.push(reader.pop_token(TokKind::Ident(ident))),
                 }
             }
         }
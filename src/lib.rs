@@ -0,0 +1,32 @@
+// Schrift is usable as a library, so that host programs can embed an Ink runtime, register
+// their own native functions, and move values between Rust and Ink.
+
+pub mod analyze;
+pub mod asm;
+pub mod bytecode;
+pub mod check;
+pub mod comp;
+pub mod convert;
+pub mod coverage;
+pub mod dap;
+pub mod debug;
+pub mod err;
+pub mod fmt;
+pub mod gen;
+pub mod golden;
+pub mod json;
+pub mod lex;
+pub mod lsp;
+pub mod optimize;
+pub mod parse;
+pub mod profile;
+pub mod rpc;
+pub mod runtime;
+#[cfg(feature = "serde")]
+pub mod ser;
+pub mod source;
+pub mod trace;
+pub mod val;
+pub mod verify;
+pub mod visit;
+pub mod vm;
@@ -0,0 +1,357 @@
+// Language Server Protocol server, behind `schrift lsp`. Editors start it
+// and talk to it over stdin and stdout in JSON-RPC messages.
+//
+// Every time a document is opened or changed, the server lexes, parses,
+// analyzes and resolves the names in it, publishes any errors as diagnostics,
+// and keeps the name resolution around to answer go-to-definition, find
+// references, hover and document symbol requests. Documents are always sent
+// in full, and nothing is shared between documents.
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::analyze::{self, BindingKind, Resolution};
+use crate::err::InkErr;
+use crate::lex::{self, Span};
+use crate::parse;
+use crate::rpc::{self, field, field_list, field_num, field_str, json_str, Json};
+use crate::runtime;
+use crate::source::SourceFile;
+use crate::val::Val;
+
+// JSON-RPC error code for requests the server does not support
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+// LSP symbol kinds
+const SYMBOL_FUNCTION: f64 = 12.0;
+const SYMBOL_VARIABLE: f64 = 13.0;
+
+struct Document {
+    file: SourceFile,
+    resolution: Resolution,
+    diagnostics: Vec<(Span, String)>,
+}
+
+impl Document {
+    fn new(uri: &str, text: &str) -> Document {
+        let mut doc = Document {
+            file: SourceFile::new(uri, text),
+            resolution: Resolution::default(),
+            diagnostics: vec![],
+        };
+
+        match check(text) {
+            Ok(resolution) => {
+                for (name, span) in resolution.undefined.iter() {
+                    doc.diagnostics
+                        .push((*span, format!("\"{}\" is not defined", name)));
+                }
+                doc.resolution = resolution;
+            }
+            Err(errors) => {
+                for (msg, span) in errors {
+                    doc.diagnostics.push((span, msg));
+                }
+            }
+        }
+        return doc;
+    }
+
+    // position converts a byte offset into an LSP position, whose line is
+    // 0-based and whose character is counted in UTF-16 code units
+    fn position(&self, offset: usize) -> Json {
+        let text = self.file.text();
+        let offset = offset.min(text.len());
+        let line = self.file.line(offset);
+        let start = self.file.line_start(line).unwrap_or(0);
+        let character = text[start..offset].encode_utf16().count();
+        return Json::Obj(vec![
+            ("line", Json::Num((line - 1) as f64)),
+            ("character", Json::Num(character as f64)),
+        ]);
+    }
+
+    fn offset(&self, pos: &Val) -> usize {
+        let line = field_num(pos, "line").unwrap_or(0);
+        let character = field_num(pos, "character").unwrap_or(0);
+        let text = self.file.text();
+        let mut offset = match self.file.line_start(line + 1) {
+            Some(start) => start,
+            None => return text.len(),
+        };
+
+        let mut units = 0;
+        for c in text[offset..].chars() {
+            if c == '\n' || units >= character {
+                break;
+            }
+            units += c.len_utf16();
+            offset += c.len_utf8();
+        }
+        return offset;
+    }
+
+    fn range(&self, span: Span) -> Json {
+        return Json::Obj(vec![
+            ("start", self.position(span.0)),
+            ("end", self.position(span.1)),
+        ]);
+    }
+
+    // binding_at returns the binding for the name at a character offset,
+    // whether it is used or defined there, with the span of the name
+    fn binding_at(&self, offset: usize) -> Option<(usize, Span)> {
+        let contains = |span: &Span| span.0 <= offset && offset <= span.1;
+        for (span, binding) in self.resolution.references.iter() {
+            if contains(span) {
+                return Some((*binding, *span));
+            }
+        }
+        for (idx, binding) in self.resolution.bindings.iter().enumerate() {
+            match binding.span {
+                Some(span) if contains(&span) => return Some((idx, span)),
+                _ => (),
+            }
+        }
+        return None;
+    }
+}
+
+// check runs the front end of the compiler over a program, and returns its
+// name resolution, or its errors with where they happened. Every syntax error
+// is returned, but checking stops at the first error after parsing.
+fn check(text: &str) -> Result<Resolution, Vec<(String, Span)>> {
+    let describe = |(err, span): (InkErr, Span)| (err.to_string(), span);
+    let tokens = lex::tokenize_spanned(text).map_err(|err| vec![describe(err)])?;
+    let mut nodes = parse::parse_all(tokens)
+        .map_err(|errors| errors.into_iter().map(describe).collect::<Vec<_>>())?;
+
+    // analysis and name resolution errors don't know where they happened
+    let at_start = |err: InkErr| vec![(err.to_string(), Span::default())];
+    let names: Vec<String> = runtime::builtins()
+        .iter()
+        .map(|builtin| builtin.name.clone())
+        .collect();
+    return analyze::analyze(&mut nodes, &names).map_err(at_start);
+}
+
+struct Server {
+    output: Box<dyn Write>,
+    docs: HashMap<String, Document>,
+}
+
+pub fn serve(mut input: Box<dyn BufRead>, output: Box<dyn Write>) {
+    let mut server = Server {
+        output,
+        docs: HashMap::new(),
+    };
+
+    while let Some(msg) = rpc::read_message(&mut *input) {
+        let method = field_str(&msg, "method");
+        let params = field(&msg, "params");
+        if method == "exit" {
+            return;
+        }
+
+        // requests have ids, and notifications don't
+        let result = match field(&msg, "id") {
+            Val::Null => {
+                server.notify(&method, &params);
+                continue;
+            }
+            _ => server.request(&method, &params),
+        };
+        let id = match field(&msg, "id") {
+            Val::Number(n) => Json::Num(n),
+            Val::Str(s) => Json::Str(String::from_utf8_lossy(&s).into_owned()),
+            _ => Json::Null,
+        };
+
+        let reply = match result {
+            Some(result) => Json::Obj(vec![
+                ("jsonrpc", json_str("2.0")),
+                ("id", id),
+                ("result", result),
+            ]),
+            None => Json::Obj(vec![
+                ("jsonrpc", json_str("2.0")),
+                ("id", id),
+                (
+                    "error",
+                    Json::Obj(vec![
+                        ("code", Json::Num(METHOD_NOT_FOUND)),
+                        (
+                            "message",
+                            Json::Str(format!("unsupported method {}", method)),
+                        ),
+                    ]),
+                ),
+            ]),
+        };
+        rpc::write_message(&mut *server.output, &reply);
+    }
+}
+
+impl Server {
+    fn notify(&mut self, method: &str, params: &Val) {
+        let text_document = field(params, "textDocument");
+        let uri = field_str(&text_document, "uri");
+        match method {
+            "textDocument/didOpen" => {
+                self.docs.insert(
+                    uri.clone(),
+                    Document::new(&uri, &field_str(&text_document, "text")),
+                );
+                self.publish_diagnostics(&uri);
+            }
+            "textDocument/didChange" => {
+                // with full document sync, the last change is the whole document
+                if let Some(change) = field_list(params, "contentChanges").last() {
+                    self.docs
+                        .insert(uri.clone(), Document::new(&uri, &field_str(change, "text")));
+                    self.publish_diagnostics(&uri);
+                }
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(&uri);
+                self.publish_diagnostics(&uri);
+            }
+            _ => (),
+        }
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) {
+        let diagnostics = match self.docs.get(uri) {
+            Some(doc) => doc
+                .diagnostics
+                .iter()
+                .map(|(span, msg)| {
+                    Json::Obj(vec![
+                        ("range", doc.range(*span)),
+                        ("severity", Json::Num(1.0)),
+                        ("source", json_str("schrift")),
+                        ("message", json_str(msg)),
+                    ])
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        let notification = Json::Obj(vec![
+            ("jsonrpc", json_str("2.0")),
+            ("method", json_str("textDocument/publishDiagnostics")),
+            (
+                "params",
+                Json::Obj(vec![
+                    ("uri", json_str(uri)),
+                    ("diagnostics", Json::Arr(diagnostics)),
+                ]),
+            ),
+        ]);
+        rpc::write_message(&mut *self.output, &notification);
+    }
+
+    // request returns the result of a request, or None if it isn't supported
+    fn request(&mut self, method: &str, params: &Val) -> Option<Json> {
+        if method == "initialize" {
+            return Some(Json::Obj(vec![
+                (
+                    "capabilities",
+                    Json::Obj(vec![
+                        ("textDocumentSync", Json::Num(1.0)),
+                        ("definitionProvider", Json::Bool(true)),
+                        ("referencesProvider", Json::Bool(true)),
+                        ("hoverProvider", Json::Bool(true)),
+                        ("documentSymbolProvider", Json::Bool(true)),
+                    ]),
+                ),
+                ("serverInfo", Json::Obj(vec![("name", json_str("schrift"))])),
+            ]));
+        }
+        if method == "shutdown" {
+            return Some(Json::Null);
+        }
+
+        let uri = field_str(&field(params, "textDocument"), "uri");
+        let doc = match self.docs.get(&uri) {
+            Some(doc) => doc,
+            None => return Some(Json::Null),
+        };
+        let location =
+            |span: Span| Json::Obj(vec![("uri", json_str(&uri)), ("range", doc.range(span))]);
+        let binding_at = || doc.binding_at(doc.offset(&field(params, "position")));
+
+        let result = match method {
+            "textDocument/definition" => match binding_at() {
+                Some((binding, _)) => match doc.resolution.bindings[binding].span {
+                    Some(span) => location(span),
+                    None => Json::Null,
+                },
+                None => Json::Null,
+            },
+            "textDocument/references" => match binding_at() {
+                Some((binding, _)) => {
+                    let mut locations = Vec::<Json>::new();
+                    let include_declaration = matches!(
+                        field(&field(params, "context"), "includeDeclaration"),
+                        Val::Bool(true)
+                    );
+                    if let Some(span) = doc.resolution.bindings[binding].span {
+                        if include_declaration {
+                            locations.push(location(span));
+                        }
+                    }
+                    for (span, reference) in doc.resolution.references.iter() {
+                        if *reference == binding {
+                            locations.push(location(*span));
+                        }
+                    }
+                    Json::Arr(locations)
+                }
+                None => Json::Null,
+            },
+            "textDocument/hover" => match binding_at() {
+                Some((binding, span)) => {
+                    let binding = &doc.resolution.bindings[binding];
+                    Json::Obj(vec![
+                        (
+                            "contents",
+                            Json::Obj(vec![
+                                ("kind", json_str("plaintext")),
+                                (
+                                    "value",
+                                    Json::Str(format!("{}: {}", binding.name, binding.kind)),
+                                ),
+                            ]),
+                        ),
+                        ("range", doc.range(span)),
+                    ])
+                }
+                None => Json::Null,
+            },
+            "textDocument/documentSymbol" => {
+                let mut symbols = Vec::<Json>::new();
+                for binding in doc.resolution.bindings.iter() {
+                    let span = match binding.span {
+                        Some(span) if binding.depth == 0 => span,
+                        _ => continue,
+                    };
+                    let kind = match binding.kind {
+                        BindingKind::Function(_) => SYMBOL_FUNCTION,
+                        _ => SYMBOL_VARIABLE,
+                    };
+                    symbols.push(Json::Obj(vec![
+                        ("name", json_str(&binding.name)),
+                        ("detail", Json::Str(binding.kind.to_string())),
+                        ("kind", Json::Num(kind)),
+                        ("range", doc.range(span)),
+                        ("selectionRange", doc.range(span)),
+                    ]));
+                }
+                Json::Arr(symbols)
+            }
+            _ => return None,
+        };
+        return Some(result);
+    }
+}
@@ -1,28 +1,38 @@
 use std::fs;
+use std::io;
 use std::path::PathBuf;
 
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-mod analyze;
+use schrift::{
+    analyze, asm, bytecode, check, coverage, dap, debug, err, fmt, gen, golden, lex, lsp, optimize,
+    parse, profile, rpc, runtime, source, trace, val, verify, vm,
+};
+
 mod args;
-mod comp;
-mod err;
-mod gen;
-mod lex;
-mod optimize;
-mod parse;
-mod runtime;
-mod val;
-mod vm;
 
 const INK_VERSION: &str = "0.1.7";
 
+const PROFILE_OUTPUT: &str = "schrift.folded";
+const PROFILE_TOP_SITES: usize = 20;
+
 fn main() {
     let opts = args::get_cli_opts();
 
     match opts.clone().action {
         args::Action::Eval(mode) => run_eval(mode, opts),
+        args::Action::Compile(path) => run_compile(path, opts),
+        args::Action::RunAsm(path) => run_asm(path, opts),
+        args::Action::Debug(path) => run_debug(path, opts),
+        args::Action::Check(path) => run_check(path, opts),
+        args::Action::Fmt(paths) => run_fmt(paths, opts),
+        args::Action::Test(path) => run_test(path, opts),
+        args::Action::Dap => run_dap(),
+        args::Action::Lsp => lsp::serve(
+            Box::new(io::BufReader::new(io::stdin())),
+            Box::new(io::stdout()),
+        ),
         args::Action::Version => print_version(),
         args::Action::Help => print_help(),
     }
@@ -39,7 +49,7 @@ fn print_help() {
 fn run_eval(mode: args::EvalMode, opts: args::Opts) {
     let result = match mode {
         args::EvalMode::RunFile(path) => eval_file(path, &opts),
-        args::EvalMode::Eval(prog) => eval_string(prog, &opts),
+        args::EvalMode::Eval(prog) => eval_string(prog, "<eval>", &opts),
         args::EvalMode::Repl => eval_repl(&opts),
     };
 
@@ -49,8 +59,248 @@ fn run_eval(mode: args::EvalMode, opts: args::Opts) {
     }
 }
 
-fn eval_file(path: PathBuf, opts: &args::Opts) -> Result<val::Val, err::InkErr> {
+fn run_compile(path: PathBuf, opts: args::Opts) {
+    let out_path = match opts.output.clone() {
+        Some(out_path) => out_path,
+        None => path.with_extension("inkb"),
+    };
+
+    let file = match fs::read(&path) {
+        Ok(prog) => prog,
+        Err(e) => {
+            eprintln!("error: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // compiling a bytecode file or listing re-encodes it, which is useful for
+    // checking that decoding and encoding round-trip exactly.
+    let is_asm = |p: &PathBuf| p.extension() == Some("inks".as_ref());
+    let blocks = if bytecode::is_bytecode(&file) {
+        bytecode::decode(&file, &runtime::builtins())
+    } else {
+        match String::from_utf8(file) {
+            Ok(listing) if is_asm(&path) => asm::assemble(&listing, &runtime::builtins()),
+            Ok(prog) => compile(
+                &source::SourceFile::new(&path.to_string_lossy(), &prog),
+                &opts,
+            ),
+            Err(_) => Err(err::InkErr::ExpectedString),
+        }
+    };
+
+    // .inks outputs are written as a textual listing for the assembler
+    let encoded = blocks.and_then(|blocks| {
+        if is_asm(&out_path) {
+            Ok(asm::disassemble(&blocks).into_bytes())
+        } else {
+            bytecode::encode(&blocks)
+        }
+    });
+    match encoded {
+        Ok(bytes) => {
+            if let Err(e) = fs::write(&out_path, bytes) {
+                eprintln!("error: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_asm(path: PathBuf, opts: args::Opts) {
     let file = match fs::read_to_string(path) {
+        Ok(listing) => listing,
+        Err(e) => {
+            eprintln!("error: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let result = asm::assemble(&file, &runtime::builtins()).and_then(|blocks| {
+        verify::verify(&blocks)?;
+        return eval_blocks(blocks, None, &opts);
+    });
+    if let Err(e) = result {
+        eprintln!("{:?}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run_debug(path: PathBuf, opts: args::Opts) {
+    let file = match fs::read_to_string(&path) {
+        Ok(prog) => source::SourceFile::new(&path.to_string_lossy(), &prog),
+        Err(e) => {
+            eprintln!("error: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let blocks = match compile(&file, &opts) {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut machine = vm::Vm::new(blocks);
+    let stdin = Box::new(io::BufReader::new(io::stdin()));
+    machine.set_debug_hook(Box::new(debug::Debugger::new(file, stdin)));
+    match machine.run() {
+        Ok(_) => println!("program exited"),
+        Err(e) => {
+            println!("program exited with error: {:?}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// run_check reports every problem the checker finds in a program, without
+// running it, as text or as a JSON array of diagnostics, and exits with an
+// error if any of them are errors
+fn run_check(path: PathBuf, opts: args::Opts) {
+    let file = match fs::read_to_string(&path) {
+        Ok(prog) => source::SourceFile::new(&path.to_string_lossy(), &prog),
+        Err(e) => {
+            eprintln!("error: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let diagnostics = check::check(file.text(), runtime::builtins());
+    if opts.json {
+        let diagnostics = diagnostics.iter().map(|d| d.json(&file)).collect();
+        println!("{}", rpc::Json::Arr(diagnostics));
+    } else {
+        for d in diagnostics.iter() {
+            println!(
+                "{}:{}: {}: {}",
+                file.name(),
+                file.position(d.span.0),
+                d.severity.name(),
+                d.message
+            );
+        }
+    }
+
+    if diagnostics
+        .iter()
+        .any(|d| d.severity == check::Severity::Error)
+    {
+        std::process::exit(1);
+    }
+}
+
+// run_fmt rewrites programs in their canonical form. With --check, it only
+// lists the programs that are not formatted. Either way, it exits with an
+// error if any program could not be formatted or, with --check, was not
+// formatted already.
+fn run_fmt(paths: Vec<PathBuf>, opts: args::Opts) {
+    let mut failed = false;
+    for path in paths.iter() {
+        let file = match fs::read_to_string(path) {
+            Ok(prog) => source::SourceFile::new(&path.to_string_lossy(), &prog),
+            Err(e) => {
+                eprintln!("error: {:?}", e);
+                failed = true;
+                continue;
+            }
+        };
+
+        let formatted = match fmt::format(file.text()) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for (err, span) in errors.iter() {
+                    eprintln!("{}:{}: error: {}", file.name(), file.position(span.0), err);
+                }
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == file.text() {
+            continue;
+        }
+
+        if opts.check {
+            println!("{}", file.name());
+            failed = true;
+        } else if let Err(e) = fs::write(path, formatted) {
+            eprintln!("error: {:?}", e);
+            failed = true;
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+// run_test runs the golden file tests under a path, and exits with an error if
+// any of them fail
+fn run_test(path: PathBuf, opts: args::Opts) {
+    let tests = match golden::discover(&path) {
+        Ok(tests) => tests,
+        Err(e) => {
+            eprintln!("error: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut passed = 0;
+    let mut failed = 0;
+    for test in tests.iter() {
+        match golden::check(test, opts.update) {
+            Ok(golden::Outcome::Pass) => {
+                println!("PASS {}", test.display());
+                passed += 1;
+            }
+            Ok(golden::Outcome::Fail(diff)) => {
+                println!("FAIL {}", test.display());
+                println!("--- {}", golden::expected_path(test).display());
+                println!("+++ output");
+                print!("{}", diff);
+                failed += 1;
+            }
+            Ok(golden::Outcome::Missing) => {
+                println!(
+                    "FAIL {} (no {}, run with --update to create it)",
+                    test.display(),
+                    golden::expected_path(test).display()
+                );
+                failed += 1;
+            }
+            Ok(golden::Outcome::Updated) => {
+                println!("UPDATED {}", golden::expected_path(test).display());
+            }
+            Err(e) => {
+                println!("FAIL {} ({:?})", test.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    if !opts.update {
+        println!("{} passed, {} failed", passed, failed);
+    }
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn run_dap() {
+    let conn = dap::Conn::new(
+        Box::new(io::BufReader::new(io::stdin())),
+        Box::new(io::stdout()),
+    );
+    dap::serve(conn);
+}
+
+fn eval_file(path: PathBuf, opts: &args::Opts) -> Result<val::Val, err::InkErr> {
+    let file = match fs::read(&path) {
         Ok(prog) => prog,
         Err(e) => {
             eprintln!("error: {:?}", e);
@@ -58,15 +308,25 @@ fn eval_file(path: PathBuf, opts: &args::Opts) -> Result<val::Val, err::InkErr>
         }
     };
 
-    return eval_string(file, opts);
+    // precompiled programs skip straight to the VM
+    if bytecode::is_bytecode(&file) {
+        let blocks = bytecode::decode(&file, &runtime::builtins())?;
+        verify::verify(&blocks)?;
+        return eval_blocks(blocks, None, opts);
+    }
+
+    return match String::from_utf8(file) {
+        Ok(prog) => eval_string(prog, &path.to_string_lossy(), opts),
+        Err(_) => Err(err::InkErr::ExpectedString),
+    };
 }
 
 fn eval_repl(opts: &args::Opts) -> Result<val::Val, err::InkErr> {
     let mut rl = Editor::<()>::new();
 
     let repl_do = |prog: String| -> Result<val::Val, err::InkErr> {
-        let optimized_blocks = compile(prog, opts)?;
-        return eval_blocks(optimized_blocks);
+        let optimized_blocks = compile(&source::SourceFile::new("<repl>", &prog), opts)?;
+        return eval_blocks(optimized_blocks, None, opts);
     };
 
     loop {
@@ -102,12 +362,12 @@ fn eval_repl(opts: &args::Opts) -> Result<val::Val, err::InkErr> {
     return Ok(val::Val::Null);
 }
 
-fn compile(prog: String, opts: &args::Opts) -> Result<Vec<gen::Block>, err::InkErr> {
-    let tokens = lex::tokenize(&prog)?;
+fn compile(file: &source::SourceFile, opts: &args::Opts) -> Result<Vec<gen::Block>, err::InkErr> {
+    let tokens = lex::tokenize(file.text())?;
     if opts.debug_lex {
         println!(":: Tokens ::");
         for (i, tok) in tokens.iter().enumerate() {
-            println!("{}  {}", i, tok);
+            println!("{}  {:?} [{}]", i, tok.kind, file.position(tok.span.0));
         }
     }
 
@@ -119,7 +379,12 @@ fn compile(prog: String, opts: &args::Opts) -> Result<Vec<gen::Block>, err::InkE
         }
     }
 
-    analyze::analyze(&mut nodes)?;
+    let builtins = runtime::builtins();
+    let names: Vec<String> = builtins
+        .iter()
+        .map(|builtin| builtin.name.clone())
+        .collect();
+    analyze::analyze(&mut nodes, &names)?;
     if opts.debug_analyze {
         println!(":: Analyzed AST nodes ::");
         for node in nodes.iter() {
@@ -127,7 +392,7 @@ fn compile(prog: String, opts: &args::Opts) -> Result<Vec<gen::Block>, err::InkE
         }
     }
 
-    let blocks = gen::generate(nodes)?;
+    let blocks = gen::generate(nodes, builtins)?;
     if opts.debug_compile {
         println!(":: Bytecode blocks ::");
         for (i, block) in blocks.iter().enumerate() {
@@ -146,12 +411,105 @@ fn compile(prog: String, opts: &args::Opts) -> Result<Vec<gen::Block>, err::InkE
     return Ok(optimized_blocks);
 }
 
-fn eval_string(prog: String, opts: &args::Opts) -> Result<val::Val, err::InkErr> {
-    let optimized_blocks = compile(prog, opts)?;
-    return eval_blocks(optimized_blocks);
+fn eval_string(prog: String, path: &str, opts: &args::Opts) -> Result<val::Val, err::InkErr> {
+    let file = source::SourceFile::new(path, &prog);
+    let optimized_blocks = compile(&file, opts)?;
+    return eval_blocks(optimized_blocks, Some(&file), opts);
 }
 
-fn eval_blocks(blocks: Vec<gen::Block>) -> Result<val::Val, err::InkErr> {
+// eval_blocks runs a compiled program. `file` is the source the program was
+// compiled from, if it was compiled from source, which coverage reports are
+// mapped to.
+fn eval_blocks(
+    blocks: Vec<gen::Block>,
+    file: Option<&source::SourceFile>,
+    opts: &args::Opts,
+) -> Result<val::Val, err::InkErr> {
     let mut machine = vm::Vm::new(blocks);
-    return machine.run();
+    if opts.profile {
+        machine.enable_profiling();
+    }
+    if opts.trace {
+        machine.set_tracer(tracer(opts));
+    }
+    if opts.coverage.is_some() {
+        machine.enable_coverage();
+    }
+
+    let result = machine.run();
+    if let Some(profiler) = machine.profiler() {
+        write_profile(profiler, opts);
+    }
+    if let Some(coverage) = machine.coverage() {
+        write_coverage(coverage, machine.prog(), file, opts);
+    }
+    return result;
+}
+
+// tracer writes to stderr unless given a file, so that the trace doesn't mix
+// with program output.
+fn tracer(opts: &args::Opts) -> trace::Tracer {
+    let out: Box<dyn io::Write> = match &opts.trace_output {
+        Some(out_path) => match fs::File::create(out_path) {
+            Ok(file) => Box::new(io::BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("error: {:?}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(io::BufWriter::new(io::stderr())),
+    };
+    let format = if opts.trace_json {
+        trace::TraceFormat::Json
+    } else {
+        trace::TraceFormat::Text
+    };
+
+    let mut tracer = trace::Tracer::new(out, format);
+    for block_idx in opts.trace_blocks.iter() {
+        tracer.filter_block(*block_idx);
+    }
+    for name in opts.trace_fns.iter() {
+        tracer.filter_name(name.clone());
+    }
+    return tracer;
+}
+
+// write_coverage saves an LCOV report of the lines, functions and match
+// clauses the program ran. Programs without source have no lines to map it to.
+fn write_coverage(
+    coverage: &coverage::Coverage,
+    prog: &[gen::Block],
+    file: Option<&source::SourceFile>,
+    opts: &args::Opts,
+) {
+    let out_path = match &opts.coverage {
+        Some(out_path) => out_path,
+        None => return,
+    };
+    let report = match file {
+        Some(file) => coverage.lcov(prog, file),
+        None => {
+            eprintln!("warning: no source to map coverage to, skipping coverage report");
+            return;
+        }
+    };
+    if let Err(e) = fs::write(out_path, report) {
+        eprintln!("error: {:?}", e);
+    }
+}
+
+// write_profile saves folded stacks for flamegraph tools, and prints a summary
+// of the hottest call sites to stderr so it doesn't mix with program output.
+fn write_profile(profiler: &profile::Profiler, opts: &args::Opts) {
+    let out_path = match opts.profile_output.clone() {
+        Some(out_path) => out_path,
+        None => PathBuf::from(PROFILE_OUTPUT),
+    };
+    if let Err(e) = fs::write(&out_path, profiler.folded()) {
+        eprintln!("error: {:?}", e);
+    }
+
+    eprintln!(":: Profile (folded stacks in {}) ::", out_path.display());
+    eprint!("{}", profiler.summary(PROFILE_TOP_SITES));
 }
@@ -1,10 +1,28 @@
 use crate::gen::Block;
+use crate::verify;
+
+type Pass = fn(Block) -> Block;
+
+// Optimizer passes, run in order over every block in the program.
+const PASSES: &[(&str, Pass)] = &[("peephole", optimize_block)];
 
 pub fn optimize(prog: Vec<Block>) -> Vec<Block> {
-    return prog
-        .iter()
-        .map(|block| optimize_block(block.clone()))
-        .collect();
+    let mut prog = prog;
+    for (name, pass) in PASSES.iter() {
+        prog = prog.into_iter().map(pass).collect();
+
+        // catch miscompilations at the pass that introduced them,
+        // rather than as a panic in the VM
+        if cfg!(debug_assertions) {
+            if let Err(e) = verify::verify(&prog) {
+                panic!(
+                    "Optimizer pass \"{}\" produced invalid bytecode: {:?}",
+                    name, e
+                );
+            }
+        }
+    }
+    return prog;
 }
 
 fn optimize_block(block: Block) -> Block {
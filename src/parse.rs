@@ -1,5 +1,11 @@
 use crate::err::InkErr;
-use crate::lex::{Tok, TokKind};
+use crate::lex::{Span, Tok, TokKind};
+use crate::visit::{self, Visitor};
+
+// MAX_DEPTH is how deeply expressions may nest, counted in nodes of the
+// syntax tree. Every pass after the parser recurses over the tree, so deeper
+// programs are a syntax error rather than a native stack overflow.
+const MAX_DEPTH: usize = 256;
 
 #[derive(Debug, Clone)]
 pub enum Node {
@@ -28,12 +34,15 @@ pub enum Node {
     },
     ExprList(Vec<Node>),
 
+    // identifiers and literals keep the span of their token in the source.
+    // Identifiers also record the binding they name, once the analyzer has
+    // resolved it.
     EmptyIdent,
-    Ident(String),
+    Ident(String, Span, Resolved),
 
-    NumberLiteral(f64),
-    StringLiteral(String),
-    BooleanLiteral(bool),
+    NumberLiteral(f64, Span),
+    StringLiteral(String, Span),
+    BooleanLiteral(bool, Span),
 
     ObjectLiteral(Vec<Node>),
     ObjectEntry {
@@ -45,9 +54,78 @@ pub enum Node {
         args: Vec<Node>,
         body: Box<Node>,
     },
+
+    // an item of a list, or of the program, with the comments around it and
+    // the span of all of its tokens. Only parse_with_comments keeps comments,
+    // for the formatter, so the compiler never sees this node.
+    Trivia {
+        node: Box<Node>,
+        span: Span,
+        // comments before the item, and any within it that no list inside it
+        // holds
+        before: Vec<Comment>,
+        // comments after the last item of a list, before its closing bracket
+        after: Vec<Comment>,
+    },
 }
 
-impl Tok<'_> {
+// Resolved is the binding an identifier names, as found by the analyzer's
+// resolution pass. Every identifier is Unresolved until then, and names of
+// properties, like `b` in `a.b` and `{b: c}`, always are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolved {
+    Unresolved,
+    // a name defined in the scope it appears in. escapes is whether any
+    // function or block inside that scope captures the name, so its value
+    // must live on the heap.
+    Local { escapes: bool },
+    // a name defined the given number of scopes out from the one it appears
+    // in
+    Captured(usize),
+    // a name no scope defines where it's read, which is one the program is
+    // compiled with: a builtin, or a variable in the debugger
+    Builtin,
+    // a name no scope defines where it's read, which the program isn't
+    // compiled with either
+    Undefined,
+}
+
+// Comment is a comment in the source. Its text and span leave out the
+// backticks around it.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+impl Node {
+    // pos returns the source offset of the leftmost identifier or literal in this
+    // node, which is where the expression begins in all but a few cases.
+    pub fn pos(&self) -> Option<usize> {
+        return match self {
+            Node::UnaryExpr { op: _, arg } => arg.pos(),
+            Node::BinaryExpr { op: _, left, right } => left.pos().or_else(|| right.pos()),
+            Node::FnCall { func, args: _ } => func.pos(),
+            Node::MatchClause { target, expr } => target.pos().or_else(|| expr.pos()),
+            Node::MatchExpr { cond, clauses: _ } => cond.pos(),
+            Node::ExprList(nodes) | Node::ObjectLiteral(nodes) | Node::ListLiteral(nodes) => {
+                nodes.iter().find_map(|node| node.pos())
+            }
+            Node::EmptyIdent => None,
+            Node::Ident(_, span, _)
+            | Node::NumberLiteral(_, span)
+            | Node::StringLiteral(_, span)
+            | Node::BooleanLiteral(_, span) => Some(span.0),
+            Node::ObjectEntry { key, val } => key.pos().or_else(|| val.pos()),
+            Node::FnLiteral { args, body } => {
+                args.iter().find_map(|arg| arg.pos()).or_else(|| body.pos())
+            }
+            Node::Trivia { node, .. } => node.pos(),
+        };
+    }
+}
+
+impl Tok {
     fn priority(&self) -> i32 {
         // higher == greater priority
         match self.kind {
@@ -71,7 +149,23 @@ impl Tok<'_> {
 
 type ParseResult = Result<Vec<Node>, InkErr>;
 
+// SyntaxErr is a syntax error, with the span of the token it was found at
+type SyntaxErr = (InkErr, Span);
+
 pub fn parse(tokens: Vec<Tok>) -> ParseResult {
+    return parse_spanned(tokens).map_err(|(err, _)| err);
+}
+
+// parse_spanned is parse, but on error also returns the span of the token the
+// parser did not expect, for tools that point at errors.
+pub fn parse_spanned(tokens: Vec<Tok>) -> Result<Vec<Node>, SyntaxErr> {
+    return parse_all(tokens).map_err(|mut errs| errs.remove(0));
+}
+
+// parse_all is parse_spanned, but returns every syntax error in the program.
+// After an error, the parser skips the rest of the expression it was in, up to
+// the next separator or the closing bracket around it, and carries on.
+pub fn parse_all(tokens: Vec<Tok>) -> Result<Vec<Node>, Vec<SyntaxErr>> {
     let tokens_without_comments: Vec<Tok> = tokens
         .into_iter()
         .filter(|tok| match tok.kind {
@@ -81,39 +175,307 @@ pub fn parse(tokens: Vec<Tok>) -> ParseResult {
         .collect();
 
     let mut parser = Parser::new(tokens_without_comments);
-    return parser.parse();
+    let nodes = parser.parse();
+    if parser.errors.is_empty() {
+        return Ok(nodes);
+    }
+    return Err(parser.errors);
 }
 
-struct Parser<'s> {
-    tokens: Vec<Tok<'s>>,
+// parse_with_comments is parse_all, but keeps the comments in the program.
+// Every item of a list, and of the program, is wrapped in a Node::Trivia that
+// holds the comments before it. Comments after the last item of a list go
+// after that item, and comments within an item but outside any list in it go
+// before the item. Comments are only returned apart from the nodes when the
+// program has no items at all to attach them to.
+pub fn parse_with_comments(tokens: Vec<Tok>) -> Result<(Vec<Node>, Vec<Comment>), Vec<SyntaxErr>> {
+    let mut comments = Vec::<Comment>::new();
+    let mut tokens_without_comments = Vec::<Tok>::new();
+    for tok in tokens.into_iter() {
+        match tok.kind {
+            TokKind::Comment(text) => comments.push(Comment {
+                text,
+                span: tok.span,
+            }),
+            _ => tokens_without_comments.push(tok),
+        }
+    }
+
+    let mut parser = Parser::new(tokens_without_comments);
+    parser.trivia = true;
+    parser.comments = comments;
+    let nodes = parser.parse();
+    if !parser.errors.is_empty() {
+        return Err(parser.errors);
+    }
+    let rest = parser.take_comments(usize::MAX);
+    return Ok((nodes, rest));
+}
+
+fn is_closing(kind: &TokKind) -> bool {
+    return matches!(kind, TokKind::RParen | TokKind::RBracket | TokKind::RBrace);
+}
+
+struct Parser {
+    tokens: Vec<Tok>,
     nodes: Vec<Node>,
     idx: usize,
+    errors: Vec<SyntaxErr>,
+    // how many atoms the parser is inside of
+    depth: usize,
+
+    // whether to wrap items in Node::Trivia, and the comments to put in them
+    trivia: bool,
+    comments: Vec<Comment>,
+    comment_idx: usize,
 }
 
-impl<'s> Parser<'s> {
+impl Parser {
     fn new(tokens: Vec<Tok>) -> Parser {
         return Parser {
             tokens,
             nodes: Vec::<Node>::new(),
             idx: 0,
+            errors: vec![],
+            depth: 0,
+            trivia: false,
+            comments: vec![],
+            comment_idx: 0,
+        };
+    }
+
+    // take_comments takes the comments not yet attached to a node that start
+    // before the given offset
+    fn take_comments(&mut self, until: usize) -> Vec<Comment> {
+        let start = self.comment_idx;
+        while self.comment_idx < self.comments.len()
+            && self.comments[self.comment_idx].span.0 < until
+        {
+            self.comment_idx += 1;
+        }
+        return self.comments[start..self.comment_idx].to_vec();
+    }
+
+    // comments_before takes the comments before the token at idx
+    fn comments_before(&mut self) -> Vec<Comment> {
+        return match self.tokens.get(self.idx) {
+            Some(tok) => {
+                let until = tok.span.0;
+                self.take_comments(until)
+            }
+            None => self.take_comments(usize::MAX),
         };
     }
 
-    fn guard_eof(&self) -> Result<(), InkErr> {
+    // item wraps an item that was parsed from the token at `start` up to idx
+    // in a Node::Trivia, when the parser keeps comments
+    fn item(&mut self, start: usize, node: Node, mut before: Vec<Comment>) -> Node {
+        if !self.trivia {
+            return node;
+        }
+
+        // the item may end with a separator it consumed
+        let mut end = self.idx - 1;
+        while end > start && self.tokens[end].kind == TokKind::Separator {
+            end -= 1;
+        }
+        let span = Span(self.tokens[start].span.0, self.tokens[end].span.1);
+        before.append(&mut self.take_comments(span.1));
+
+        return Node::Trivia {
+            node: Box::new(node),
+            span,
+            before,
+            after: vec![],
+        };
+    }
+
+    // attach_after attaches the comments before the token at idx to the last
+    // of the given items. Without any items, the comments are left for the
+    // item around them.
+    fn attach_after(&mut self, items: &mut [Node]) {
+        if let Some(Node::Trivia { after, .. }) = items.last_mut() {
+            after.append(&mut self.comments_before());
+        }
+    }
+
+    fn guard_eof(&self) -> Result<(), SyntaxErr> {
         if self.idx >= self.tokens.len() {
-            return Err(InkErr::UnexpectedEOF);
+            return Err(self.eof());
         } else {
             return Ok(());
         }
     }
 
-    fn parse(&mut self) -> ParseResult {
+    // eof returns the error for a program that ends in the middle of an
+    // expression, at its last token
+    fn eof(&self) -> SyntaxErr {
+        return match self.tokens.last() {
+            Some(tok) => (InkErr::UnexpectedEOF, tok.span),
+            None => (InkErr::UnexpectedEOF, Span::default()),
+        };
+    }
+
+    // unexpected returns the error for finding the token at idx when the
+    // parser expected something else
+    fn unexpected(&self, expected: &str) -> SyntaxErr {
+        return match self.tokens.get(self.idx) {
+            Some(tok) => (
+                InkErr::UnexpectedToken {
+                    expected: expected.to_string(),
+                    found: tok.kind.to_string(),
+                },
+                tok.span,
+            ),
+            None => self.eof(),
+        };
+    }
+
+    // too_deep returns the error for an expression nested more than
+    // MAX_DEPTH deep, at the token at idx
+    fn too_deep(&self, idx: usize) -> SyntaxErr {
+        return match self.tokens.get(idx) {
+            Some(tok) => (InkErr::TooDeeplyNested(MAX_DEPTH), tok.span),
+            None => self.eof(),
+        };
+    }
+
+    // check_depth returns the node, or an error at span if its tree is deeper
+    // than MAX_DEPTH, counting the atoms the parser is inside of
+    fn check_depth(&self, span: Span, node: Node) -> Result<Node, SyntaxErr> {
+        let mut depth = DepthCheck {
+            depth: self.depth,
+            too_deep: false,
+        };
+        depth.visit(&node);
+        if depth.too_deep {
+            return Err((InkErr::TooDeeplyNested(MAX_DEPTH), span));
+        }
+        return Ok(node);
+    }
+
+    // expect consumes a token of the given kind, or returns an error
+    fn expect(&mut self, kind: TokKind) -> Result<(), SyntaxErr> {
+        match self.tokens.get(self.idx) {
+            Some(tok) if tok.kind == kind => {
+                self.idx += 1;
+                return Ok(());
+            }
+            _ => return Err(self.unexpected(&kind.to_string())),
+        }
+    }
+
+    // report records a syntax error. Only the first error at a token is kept,
+    // the end of the program is reported once, however many brackets are
+    // left open there, and an expression nested too deeply is reported at its
+    // deepest part, not again for each expression around it.
+    fn report(&mut self, err: SyntaxErr) {
+        let reported = self.errors.iter().any(|(e, span)| {
+            *span == err.1 || matches!((e, &err.0), (InkErr::UnexpectedEOF, InkErr::UnexpectedEOF))
+        }) || match (self.errors.last(), &err) {
+            (Some((InkErr::TooDeeplyNested(_), last)), (InkErr::TooDeeplyNested(_), span)) => {
+                last.0 >= span.0
+            }
+            _ => false,
+        };
+        if !reported {
+            self.errors.push(err);
+        }
+    }
+
+    // recover reports an error in the expression that starts at `start`, and
+    // skips past the rest of it: up to and including the next separator, or up
+    // to the closing bracket of the list the expression is in. Brackets opened
+    // within the expression are skipped whole.
+    fn recover(&mut self, start: usize, err: SyntaxErr) {
+        self.report(err);
+
+        self.idx = start;
+        let mut depth = 0;
+        while self.idx < self.tokens.len() {
+            match self.tokens[self.idx].kind {
+                TokKind::LParen | TokKind::LBracket | TokKind::LBrace => depth += 1,
+                TokKind::RParen | TokKind::RBracket | TokKind::RBrace => {
+                    if depth == 0 {
+                        return;
+                    }
+                    depth -= 1;
+                }
+                TokKind::Separator if depth == 0 => {
+                    self.idx += 1;
+                    return;
+                }
+                _ => (),
+            }
+            self.idx += 1;
+        }
+    }
+
+    fn parse(&mut self) -> Vec<Node> {
         while self.idx < self.tokens.len() {
-            let node = self.parse_expr()?;
-            self.nodes.push(node);
+            let start = self.idx;
+            let before = self.comments_before();
+            let span = self.tokens[start].span;
+            match self
+                .parse_expr()
+                .and_then(|node| self.check_depth(span, node))
+            {
+                Ok(node) => {
+                    let node = self.item(start, node, before);
+                    self.nodes.push(node);
+                }
+                Err(err) => {
+                    self.recover(start, err);
+
+                    // a closing bracket with no opening bracket before it
+                    if self.idx < self.tokens.len() && is_closing(&self.tokens[self.idx].kind) {
+                        self.idx += 1;
+                        self.consume_dangling_separator();
+                    }
+                }
+            }
         }
 
-        return Ok(self.nodes.clone());
+        let mut nodes = self.nodes.clone();
+        self.attach_after(&mut nodes);
+        return nodes;
+    }
+
+    // parse_list parses items up to a closing bracket, and consumes it. An
+    // item with a syntax error is skipped, so that the rest of the list is
+    // still parsed. A list closed by the wrong kind of bracket is reported, and
+    // the bracket is left for the list around it.
+    fn parse_list(
+        &mut self,
+        close: TokKind,
+        parse_item: fn(&mut Parser) -> Result<Node, SyntaxErr>,
+    ) -> Result<Vec<Node>, SyntaxErr> {
+        let mut items = Vec::<Node>::new();
+        loop {
+            self.guard_eof()?;
+
+            let kind = &self.tokens[self.idx].kind;
+            if *kind == close {
+                self.attach_after(&mut items);
+                self.idx += 1;
+                return Ok(items);
+            }
+            if is_closing(kind) {
+                let err = self.unexpected(&close.to_string());
+                self.report(err);
+                return Ok(items);
+            }
+
+            let start = self.idx;
+            let before = self.comments_before();
+            match parse_item(self) {
+                Ok(item) => {
+                    let item = self.item(start, item, before);
+                    items.push(item);
+                }
+                Err(err) => self.recover(start, err),
+            }
+        }
     }
 
     fn consume_dangling_separator(&mut self) {
@@ -122,7 +484,21 @@ impl<'s> Parser<'s> {
         }
     }
 
-    fn parse_expr(&mut self) -> Result<Node, InkErr> {
+    // end_expr consumes the separator after an expression. Only a closing
+    // parenthesis, the ':' after an object key or the end of the program may
+    // take its place, and those are left for the parent to consume.
+    fn end_expr(&mut self) -> Result<(), SyntaxErr> {
+        return match self.tokens.get(self.idx).map(|tok| &tok.kind) {
+            None | Some(TokKind::RParen) | Some(TokKind::KeyValueSeparator) => Ok(()),
+            Some(TokKind::Separator) => {
+                self.idx += 1;
+                Ok(())
+            }
+            Some(_) => Err(self.unexpected("separator or operator")),
+        };
+    }
+
+    fn parse_expr(&mut self) -> Result<Node, SyntaxErr> {
         let atom = self.parse_atom()?;
 
         self.guard_eof()?;
@@ -148,7 +524,7 @@ impl<'s> Parser<'s> {
                     self.idx += 1; // MatchColon
 
                     let clauses = self.parse_match_body()?;
-                    self.consume_dangling_separator();
+                    self.end_expr()?;
 
                     return Ok(Node::MatchExpr {
                         cond: Box::new(bin_expr),
@@ -156,19 +532,22 @@ impl<'s> Parser<'s> {
                     });
                 }
 
-                self.consume_dangling_separator();
+                self.end_expr()?;
                 return Ok(bin_expr);
             }
             TokKind::MatchColon => {
                 let clauses = self.parse_match_body()?;
-                self.consume_dangling_separator();
+                self.end_expr()?;
 
                 return Ok(Node::MatchExpr {
                     cond: Box::new(atom),
                     clauses: clauses,
                 });
             }
-            _ => Err(InkErr::UnexpectedToken),
+            _ => {
+                self.idx -= 1;
+                return Err(self.unexpected("separator or operator"));
+            }
         }
     }
 
@@ -177,7 +556,8 @@ impl<'s> Parser<'s> {
         left: Node,
         op: Tok,
         prev_priority: i32,
-    ) -> Result<Node, InkErr> {
+    ) -> Result<Node, SyntaxErr> {
+        let span = op.span;
         let right = self.parse_atom()?;
 
         let mut ops = vec![op];
@@ -193,6 +573,9 @@ impl<'s> Parser<'s> {
             } else if ops.last().unwrap().priority() >= self.tokens[self.idx].priority() {
                 // Priority is lower than the previous op but higher than parent,
                 // so it's ok to be left-heavy in this tree
+                if ops.len() == MAX_DEPTH {
+                    return Err(self.too_deep(self.idx));
+                }
                 ops.push(self.tokens[self.idx].clone());
                 self.idx += 1;
                 self.guard_eof()?;
@@ -216,26 +599,33 @@ impl<'s> Parser<'s> {
         }
 
         // ops, nodes -> left-biased binary expression tree
-        let mut tree = nodes[0].clone();
-        let mut nodes_slice = &nodes[1..];
-        let mut ops_slice = &ops[..];
-
-        while ops_slice.len() > 0 {
+        let mut nodes = nodes.into_iter();
+        let mut tree = nodes.next().unwrap();
+        for (op, node) in ops.into_iter().zip(nodes) {
             tree = Node::BinaryExpr {
-                op: ops_slice[0].clone().kind,
-                left: Box::new(tree.clone()),
-                right: Box::new(nodes_slice[0].clone()),
+                op: op.kind,
+                left: Box::new(tree),
+                right: Box::new(node),
             };
-
-            ops_slice = &ops_slice[1..];
-            nodes_slice = &nodes_slice[1..];
         }
 
-        return Ok(tree);
+        return self.check_depth(span, tree);
     }
 
     // parse_atom returns its result instead of pushing to self.nodes
-    fn parse_atom(&mut self) -> Result<Node, InkErr> {
+    fn parse_atom(&mut self) -> Result<Node, SyntaxErr> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.too_deep(self.idx));
+        }
+        self.depth += 1;
+        let atom = self.parse_nested_atom();
+        self.depth -= 1;
+        return atom;
+    }
+
+    // parse_nested_atom parses an atom, once parse_atom has checked that it
+    // isn't nested too deeply
+    fn parse_nested_atom(&mut self) -> Result<Node, SyntaxErr> {
         self.guard_eof()?;
 
         let tok = self.tokens[self.idx].clone();
@@ -253,10 +643,10 @@ impl<'s> Parser<'s> {
 
         let mut atom: Node;
         match tok.kind.clone() {
-            TokKind::NumberLiteral(num) => return Ok(Node::NumberLiteral(num)),
-            TokKind::StringLiteral(s) => return Ok(Node::StringLiteral(s)),
-            TokKind::TrueLiteral => return Ok(Node::BooleanLiteral(true)),
-            TokKind::FalseLiteral => return Ok(Node::BooleanLiteral(false)),
+            TokKind::NumberLiteral(num) => return Ok(Node::NumberLiteral(num, tok.span)),
+            TokKind::StringLiteral(s) => return Ok(Node::StringLiteral(s, tok.span)),
+            TokKind::TrueLiteral => return Ok(Node::BooleanLiteral(true, tok.span)),
+            TokKind::FalseLiteral => return Ok(Node::BooleanLiteral(false, tok.span)),
             TokKind::Ident(s) => {
                 if self.tokens[self.idx].kind == TokKind::FunctionArrow {
                     self.idx -= 1;
@@ -267,7 +657,7 @@ impl<'s> Parser<'s> {
                     // so we backtrack one token.
                     self.idx -= 1;
                 } else {
-                    atom = Node::Ident(s)
+                    atom = Node::Ident(s, tok.span, Resolved::Unresolved)
                 }
                 // fallthrough
             }
@@ -286,16 +676,17 @@ impl<'s> Parser<'s> {
             }
             TokKind::LParen => {
                 // expression list, or argument list for a function literal
-                let mut exprs = Vec::<Node>::new();
                 let lparen_idx = self.idx - 1;
-                while self.tokens[self.idx].kind != TokKind::RParen {
-                    exprs.push(self.parse_expr()?);
-                    self.guard_eof()?;
-                }
-                self.idx += 1; // RParen
+                let errors_len = self.errors.len();
+                let comment_idx = self.comment_idx;
+                let exprs = self.parse_list(TokKind::RParen, Parser::parse_expr)?;
                 self.guard_eof()?;
 
                 if self.tokens[self.idx].kind == TokKind::FunctionArrow {
+                    // the arguments are parsed again, and errors in them are
+                    // reported as arguments rather than as expressions
+                    self.errors.truncate(errors_len);
+                    self.comment_idx = comment_idx;
                     self.idx = lparen_idx;
                     atom = self.parse_fn_literal_variadic()?;
 
@@ -309,78 +700,59 @@ impl<'s> Parser<'s> {
                 // fallthrough
             }
             TokKind::LBrace => {
-                let mut entries = Vec::<Node>::new();
-                while self.tokens[self.idx].kind != TokKind::RBrace {
-                    let key_expr = self.parse_expr()?;
-                    self.guard_eof()?;
-
-                    if self.tokens[self.idx].kind == TokKind::KeyValueSeparator {
-                        self.idx += 1; // KeyValueSeparator
-                    } else {
-                        return Err(InkErr::ExpectedCompositeValue);
-                    }
-
-                    self.guard_eof()?;
-
-                    let val_expr = self.parse_expr()?;
-
-                    // Separator after val_expr is consumed by parse_expr
-                    entries.push(Node::ObjectEntry {
-                        key: Box::new(key_expr),
-                        val: Box::new(val_expr),
-                    });
-
-                    self.guard_eof()?;
-                }
-                self.idx += 1; // RBrace
-
+                let entries = self.parse_list(TokKind::RBrace, Parser::parse_object_entry)?;
                 return Ok(Node::ObjectLiteral(entries));
             }
             TokKind::LBracket => {
-                let mut items = Vec::<Node>::new();
-                while self.tokens[self.idx].kind != TokKind::RBracket {
-                    items.push(self.parse_expr()?);
-                    self.guard_eof()?;
-                }
-                self.idx += 1; // RBracket
-
+                let items = self.parse_list(TokKind::RBracket, Parser::parse_expr)?;
                 return Ok(Node::ListLiteral(items));
             }
-            _ => return Err(InkErr::UnexpectedToken),
+            _ => {
+                self.idx -= 1;
+                return Err(self.unexpected("an expression"));
+            }
         }
 
         // bounds check here because parse_expr may have consumed all tokens before this
+        let span = self.tokens.get(self.idx).map(|tok| tok.span);
+        let mut calls = 0;
         while self.idx < self.tokens.len() && self.tokens[self.idx].kind == TokKind::LParen {
+            if calls == MAX_DEPTH {
+                return Err(self.too_deep(self.idx));
+            }
             atom = self.parse_fn_call(atom)?;
+            calls += 1;
             self.guard_eof()?;
         }
 
-        return Ok(atom);
+        return match span {
+            Some(span) if calls > 0 => self.check_depth(span, atom),
+            _ => Ok(atom),
+        };
     }
 
-    fn parse_match_body(&mut self) -> Result<Vec<Node>, InkErr> {
-        self.idx += 1; // LBrace
-        let mut clauses = Vec::<Node>::new();
-
+    fn parse_object_entry(&mut self) -> Result<Node, SyntaxErr> {
+        let key_expr = self.parse_expr()?;
+        self.expect(TokKind::KeyValueSeparator)?;
         self.guard_eof()?;
 
-        while self.tokens[self.idx].kind != TokKind::RBrace {
-            clauses.push(self.parse_match_clause()?);
-            self.guard_eof()?;
-        }
-        self.idx += 1; // RBrace
+        // Separator after val_expr is consumed by parse_expr
+        let val_expr = self.parse_expr()?;
 
-        return Ok(clauses);
+        return Ok(Node::ObjectEntry {
+            key: Box::new(key_expr),
+            val: Box::new(val_expr),
+        });
     }
 
-    fn parse_match_clause(&mut self) -> Result<Node, InkErr> {
-        let atom = self.parse_atom()?;
-        self.guard_eof()?;
+    fn parse_match_body(&mut self) -> Result<Vec<Node>, SyntaxErr> {
+        self.expect(TokKind::LBrace)?;
+        return self.parse_list(TokKind::RBrace, Parser::parse_match_clause);
+    }
 
-        if self.tokens[self.idx].kind != TokKind::CaseArrow {
-            return Err(InkErr::ExpectedMatchCaseArrow);
-        }
-        self.idx += 1; // CaseArrow
+    fn parse_match_clause(&mut self) -> Result<Node, SyntaxErr> {
+        let atom = self.parse_atom()?;
+        self.expect(TokKind::CaseArrow)?;
         self.guard_eof()?;
 
         let expr = self.parse_expr()?;
@@ -391,22 +763,24 @@ impl<'s> Parser<'s> {
         });
     }
 
-    fn parse_fn_literal_monadic(&mut self) -> Result<Node, InkErr> {
-        let mut args = Vec::<Node>::new();
+    // parse_fn_arg parses the name of a function literal's argument
+    fn parse_fn_arg(&mut self) -> Result<Node, SyntaxErr> {
+        self.guard_eof()?;
 
-        let kind = &self.tokens[self.idx].kind;
-        match kind {
-            TokKind::Ident(s) => args.push(Node::Ident(s.clone())),
-            TokKind::EmptyIdent => args.push(Node::EmptyIdent),
-            _ => return Err(InkErr::UnexpectedArgument),
-        }
+        let tok = &self.tokens[self.idx];
+        let arg = match &tok.kind {
+            TokKind::Ident(s) => Node::Ident(s.clone(), tok.span, Resolved::Unresolved),
+            TokKind::EmptyIdent => Node::EmptyIdent,
+            _ => return Err(self.unexpected("an argument name")),
+        };
         self.idx += 1; // [Empty]Ident
-        self.guard_eof()?;
 
-        if self.tokens[self.idx].kind != TokKind::FunctionArrow {
-            return Err(InkErr::UnexpectedToken);
-        }
-        self.idx += 1; // FunctionArrow
+        return Ok(arg);
+    }
+
+    fn parse_fn_literal_monadic(&mut self) -> Result<Node, SyntaxErr> {
+        let args = vec![self.parse_fn_arg()?];
+        self.expect(TokKind::FunctionArrow)?;
 
         let body = self.parse_expr()?;
 
@@ -416,40 +790,24 @@ impl<'s> Parser<'s> {
         });
     }
 
-    fn parse_fn_literal_variadic(&mut self) -> Result<Node, InkErr> {
+    fn parse_fn_literal_variadic(&mut self) -> Result<Node, SyntaxErr> {
         self.idx += 1; // LParen
 
         let mut args = Vec::<Node>::new();
         while self.tokens[self.idx].kind != TokKind::RParen {
-            let kind = &self.tokens[self.idx].kind;
-            match kind {
-                TokKind::Ident(s) => args.push(Node::Ident(s.clone())),
-                TokKind::EmptyIdent => args.push(Node::EmptyIdent),
-                _ => return Err(InkErr::UnexpectedArgument),
-            }
-            self.idx += 1; // [Empty]Ident
+            args.push(self.parse_fn_arg()?);
             self.guard_eof()?;
 
             if self.tokens[self.idx].kind != TokKind::Separator {
-                return Err(InkErr::UnexpectedToken);
+                return Err(self.unexpected("',' or ')'"));
             }
 
             self.idx += 1; // Separator
 
             // guard_eof not necessary here because a file always ends with Separator
         }
-        self.guard_eof()?;
-
-        if self.tokens[self.idx].kind != TokKind::RParen {
-            return Err(InkErr::UnexpectedToken);
-        }
-        self.idx += 1; // RParen
-        self.guard_eof()?;
-
-        if self.tokens[self.idx].kind != TokKind::FunctionArrow {
-            return Err(InkErr::UnexpectedToken);
-        }
-        self.idx += 1; // FunctionArrow
+        self.expect(TokKind::RParen)?;
+        self.expect(TokKind::FunctionArrow)?;
 
         let body = self.parse_expr()?;
 
@@ -459,17 +817,11 @@ impl<'s> Parser<'s> {
         });
     }
 
-    fn parse_fn_call(&mut self, func: Node) -> Result<Node, InkErr> {
+    fn parse_fn_call(&mut self, func: Node) -> Result<Node, SyntaxErr> {
         self.idx += 1; // LParen
         self.guard_eof()?;
 
-        let mut args = Vec::<Node>::new();
-
-        while self.tokens[self.idx].kind != TokKind::RParen {
-            args.push(self.parse_expr()?);
-            self.guard_eof()?;
-        }
-        self.idx += 1; // RParen
+        let args = self.parse_list(TokKind::RParen, Parser::parse_expr)?;
 
         return Ok(Node::FnCall {
             func: Box::new(func),
@@ -477,3 +829,30 @@ impl<'s> Parser<'s> {
         });
     }
 }
+
+// DepthCheck finds whether a tree is deeper than MAX_DEPTH, without
+// descending any further than that
+struct DepthCheck {
+    depth: usize,
+    too_deep: bool,
+}
+
+impl Visitor for DepthCheck {
+    fn visit(&mut self, node: &Node) {
+        if self.too_deep {
+            return;
+        }
+        // trivia only wraps items when formatting, so doesn't count toward
+        // the depth of a program
+        if let Node::Trivia { node, .. } = node {
+            return self.visit(node);
+        }
+        if self.depth == MAX_DEPTH {
+            self.too_deep = true;
+            return;
+        }
+        self.depth += 1;
+        visit::walk(self, node);
+        self.depth -= 1;
+    }
+}
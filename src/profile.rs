@@ -0,0 +1,233 @@
+// Instrumenting profiler for the VM. When profiling is enabled, the VM
+// attributes every instruction it executes, and all wall time, to the call
+// stack that was running at the time.
+//
+// Call stacks are kept as a tree of nodes, one per distinct path of calls from
+// the main block. Each stack frame remembers its node, so recording an
+// instruction is a single counter increment, and switching stacks only happens
+// on calls and returns. Native function calls get their own nodes, so time
+// spent in (say) wait is visible, and Ink callbacks from native functions are
+// nested under them.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::gen::Block;
+
+pub const ROOT: usize = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Site {
+    Root,
+    Block(usize),
+    Native(String),
+}
+
+#[derive(Debug)]
+struct Node {
+    site: Site,
+    parent: usize,
+    children: HashMap<Site, usize>,
+
+    // counts and times are "self" values, excluding any callees
+    insts: u64,
+    time: Duration,
+}
+
+#[derive(Debug)]
+pub struct Profiler {
+    block_names: Vec<String>,
+    nodes: Vec<Node>,
+    current: usize,
+    last_switch: Instant,
+}
+
+struct SiteStats {
+    site: Site,
+    insts: u64,
+    self_time: Duration,
+    total_time: Duration,
+}
+
+impl Profiler {
+    pub fn new(prog: &[Block]) -> Profiler {
+        return Profiler {
+            block_names: prog.iter().map(|block| block.name.clone()).collect(),
+            nodes: vec![Node {
+                site: Site::Root,
+                parent: ROOT,
+                children: HashMap::new(),
+                insts: 0,
+                time: Duration::new(0, 0),
+            }],
+            current: ROOT,
+            last_switch: Instant::now(),
+        };
+    }
+
+    // label names a call site in profiler output. Blocks are labeled by their
+    // index in --debug-compile listings, and by function name if they have one.
+    fn label(&self, site: &Site) -> String {
+        return match site {
+            Site::Root => "root".to_string(),
+            Site::Block(0) => "main".to_string(),
+            Site::Block(idx) => match self.block_names.get(*idx) {
+                Some(name) if !name.is_empty() => format!("{}#{}", name, idx),
+                _ => format!("#{}", idx),
+            },
+            Site::Native(name) => name.clone(),
+        };
+    }
+
+    pub fn current(&self) -> usize {
+        return self.current;
+    }
+
+    pub fn parent(&self, node: usize) -> usize {
+        return self.nodes[node].parent;
+    }
+
+    // child returns the node for calling `site` from the stack at `parent`,
+    // creating it the first time that call is seen.
+    pub fn child(&mut self, parent: usize, site: Site) -> usize {
+        if let Some(node) = self.nodes[parent].children.get(&site) {
+            return *node;
+        }
+
+        let node = self.nodes.len();
+        self.nodes[parent].children.insert(site.clone(), node);
+        self.nodes.push(Node {
+            site,
+            parent,
+            children: HashMap::new(),
+            insts: 0,
+            time: Duration::new(0, 0),
+        });
+        return node;
+    }
+
+    // enter attributes the time since the last switch to the current stack,
+    // and makes `node` the current stack.
+    pub fn enter(&mut self, node: usize) {
+        let now = Instant::now();
+        self.nodes[self.current].time += now - self.last_switch;
+        self.last_switch = now;
+        self.current = node;
+    }
+
+    pub fn count_inst(&mut self) {
+        self.nodes[self.current].insts += 1;
+    }
+
+    fn stack_labels(&self, node: usize) -> Vec<String> {
+        let mut labels = Vec::<String>::new();
+        let mut idx = node;
+        while idx != ROOT {
+            labels.push(self.label(&self.nodes[idx].site));
+            idx = self.nodes[idx].parent;
+        }
+        labels.reverse();
+        return labels;
+    }
+
+    // folded formats the profile as "folded stacks", one line per call stack
+    // with its self time in microseconds, as read by flamegraph.pl, inferno
+    // and speedscope.
+    pub fn folded(&self) -> String {
+        let mut lines = Vec::<String>::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let micros = node.time.as_micros();
+            if idx == ROOT || micros == 0 {
+                continue;
+            }
+            lines.push(format!("{} {}", self.stack_labels(idx).join(";"), micros));
+        }
+        lines.sort();
+
+        let mut out = String::new();
+        for line in lines {
+            out.push_str(&line);
+            out.push('\n');
+        }
+        return out;
+    }
+
+    fn site_stats(&self) -> Vec<SiteStats> {
+        let mut stats = Vec::<SiteStats>::new();
+        let mut stat_idx = HashMap::<Site, usize>::new();
+        let mut stat_for = |stats: &mut Vec<SiteStats>, site: &Site| -> usize {
+            if let Some(idx) = stat_idx.get(site) {
+                return *idx;
+            }
+            stats.push(SiteStats {
+                site: site.clone(),
+                insts: 0,
+                self_time: Duration::new(0, 0),
+                total_time: Duration::new(0, 0),
+            });
+            stat_idx.insert(site.clone(), stats.len() - 1);
+            return stats.len() - 1;
+        };
+
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if idx == ROOT {
+                continue;
+            }
+
+            let own = stat_for(&mut stats, &node.site);
+            stats[own].insts += node.insts;
+            stats[own].self_time += node.time;
+
+            // total time counts a node's self time once towards every site on its
+            // stack, even if a recursive site appears on the stack more than once
+            let mut seen = Vec::<Site>::new();
+            let mut ancestor = idx;
+            while ancestor != ROOT {
+                let site = &self.nodes[ancestor].site;
+                if !seen.contains(site) {
+                    let stat = stat_for(&mut stats, site);
+                    stats[stat].total_time += node.time;
+                    seen.push(site.clone());
+                }
+                ancestor = self.nodes[ancestor].parent;
+            }
+        }
+        return stats;
+    }
+
+    // summary formats a table of the `top` call sites with the most self time
+    pub fn summary(&self, top: usize) -> String {
+        let mut stats = self.site_stats();
+        stats.sort_by(|a, b| b.self_time.cmp(&a.self_time).then(b.insts.cmp(&a.insts)));
+
+        let total_insts: u64 = stats.iter().map(|stat| stat.insts).sum();
+        let total_time: Duration = stats.iter().map(|stat| stat.self_time).sum();
+        let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+
+        let mut out = format!(
+            "{:<16}{:>12}{:>14}{:>10}{:>14}\n",
+            "site", "insts", "self ms", "self %", "total ms"
+        );
+        for stat in stats.iter().take(top) {
+            let percent = if total_time.as_nanos() == 0 {
+                0.0
+            } else {
+                100.0 * stat.self_time.as_secs_f64() / total_time.as_secs_f64()
+            };
+            out.push_str(&format!(
+                "{:<16}{:>12}{:>14.3}{:>10.1}{:>14.3}\n",
+                self.label(&stat.site),
+                stat.insts,
+                millis(stat.self_time),
+                percent,
+                millis(stat.total_time)
+            ));
+        }
+        out.push_str(&format!(
+            "{} instructions in {:.3} ms\n",
+            total_insts,
+            millis(total_time)
+        ));
+        return out;
+    }
+}
@@ -0,0 +1,137 @@
+// Message transport for the debug adapter and language servers. Both protocols
+// exchange JSON messages over stdin and stdout, each preceded by headers with
+// its Content-Length in bytes and a blank line.
+//
+// Incoming messages are parsed into Ink values by the json module, and read
+// with the field helpers here. Outgoing messages are built as Json values.
+
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use crate::json;
+use crate::val::Val;
+
+// Json is an outgoing message. Unlike Ink composites, it tells empty arrays
+// apart from empty objects, and keeps object fields in order.
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(&'static str, Json)>),
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Num(n) => write!(f, "{}", n),
+            Json::Str(s) => write_str(f, s),
+            Json::Arr(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Obj(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{}", val)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// write_str escapes all control characters, since source text and program
+// output in messages may contain any of them
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+pub fn json_str(s: &str) -> Json {
+    return Json::Str(s.to_string());
+}
+
+// read_message returns the next message, or None at the end of the input or
+// if the message is malformed
+pub fn read_message(input: &mut dyn BufRead) -> Option<Val> {
+    let mut len: Option<usize> = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(len_str) = header.strip_prefix("Content-Length:") {
+            len = len_str.trim().parse().ok();
+        }
+    }
+
+    let mut body = vec![0; len?];
+    input.read_exact(&mut body).ok()?;
+    return json::parse(&body);
+}
+
+// write_message is best-effort, like trace output, since a client that stopped
+// reading can't be told about the error
+pub fn write_message(output: &mut dyn Write, msg: &Json) {
+    let body = msg.to_string();
+    let _ = write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = output.flush();
+}
+
+// field reads a field of an incoming message, or null if it is missing
+pub fn field(msg: &Val, key: &str) -> Val {
+    return match msg {
+        Val::Comp(comp_rc) => comp_rc.borrow().get(&Val::Str(key.as_bytes().to_vec())),
+        _ => Val::Null,
+    };
+}
+
+pub fn field_str(msg: &Val, key: &str) -> String {
+    return match field(msg, key) {
+        Val::Str(s) => String::from_utf8_lossy(&s).into_owned(),
+        _ => String::new(),
+    };
+}
+
+pub fn field_num(msg: &Val, key: &str) -> Option<usize> {
+    return match field(msg, key) {
+        Val::Number(n) if n >= 0.0 => Some(n as usize),
+        _ => None,
+    };
+}
+
+pub fn field_list(msg: &Val, key: &str) -> Vec<Val> {
+    return match field(msg, key) {
+        Val::Comp(comp_rc) => comp_rc.borrow().as_list().unwrap_or_default(),
+        _ => vec![],
+    };
+}
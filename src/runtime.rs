@@ -1,11 +1,15 @@
 use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
 
 use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::err::InkErr;
 use crate::comp::Comp;
-use crate::val::Val;
+use crate::json;
+use crate::val::{NativeFn, Val};
+use crate::vm::VmContext;
 
 pub fn neg(v: &Val) -> Result<Val, InkErr> {
     let result = match v {
@@ -210,10 +214,9 @@ pub fn gtr(a: &Val, b: &Val) -> Result<Val, InkErr> {
             Val::Number(num_b) => Val::Bool(num_a > num_b),
             _ => return Err(InkErr::InvalidOperand),
         },
-        Val::Str(_bytes_a) => match b {
-            Val::Str(_bytes_b) => {
-                return Err(InkErr::InvalidOperand);
-            }
+        // strings compare byte by byte, like Go's bytes.Compare
+        Val::Str(bytes_a) => match b {
+            Val::Str(bytes_b) => Val::Bool(bytes_a > bytes_b),
             _ => return Err(InkErr::InvalidOperand),
         },
         _ => return Err(InkErr::InvalidOperand),
@@ -228,10 +231,9 @@ pub fn lss(a: &Val, b: &Val) -> Result<Val, InkErr> {
             Val::Number(num_b) => Val::Bool(num_a < num_b),
             _ => return Err(InkErr::InvalidOperand),
         },
-        Val::Str(_bytes_a) => match b {
-            Val::Str(_bytes_b) => {
-                return Err(InkErr::InvalidOperand);
-            }
+        // strings compare byte by byte, like Go's bytes.Compare
+        Val::Str(bytes_a) => match b {
+            Val::Str(bytes_b) => Val::Bool(bytes_a < bytes_b),
             _ => return Err(InkErr::InvalidOperand),
         },
         _ => return Err(InkErr::InvalidOperand),
@@ -246,7 +248,22 @@ pub fn eql(a: &Val, b: &Val) -> Result<Val, InkErr> {
 
 // runtime builtins
 
-pub fn builtin_out(args: Vec<Val>) -> Result<Val, InkErr> {
+// builtins returns the native functions available in the global scope of every Ink program.
+// Embedders may append their own NativeFn's to this list before code generation.
+pub fn builtins() -> Vec<NativeFn> {
+    return vec![
+        NativeFn::new("out", builtin_out),
+        NativeFn::new("char", builtin_char),
+        NativeFn::new("string", builtin_string),
+        NativeFn::new("len", builtin_len),
+        NativeFn::new("load", builtin_load),
+        NativeFn::new("wait", builtin_wait),
+        NativeFn::new("jsonSerialize", builtin_json_serialize),
+        NativeFn::new("jsonParse", builtin_json_parse),
+    ];
+}
+
+pub fn builtin_out(_ctx: &mut dyn VmContext, args: Vec<Val>) -> Result<Val, InkErr> {
     if args.len() < 1 {
         return Err(InkErr::NotEnoughArguments);
     }
@@ -261,7 +278,7 @@ pub fn builtin_out(args: Vec<Val>) -> Result<Val, InkErr> {
     };
 }
 
-pub fn builtin_char(args: Vec<Val>) -> Result<Val, InkErr> {
+pub fn builtin_char(_ctx: &mut dyn VmContext, args: Vec<Val>) -> Result<Val, InkErr> {
     if args.len() < 1 {
         return Err(InkErr::NotEnoughArguments);
     }
@@ -276,7 +293,7 @@ pub fn builtin_char(args: Vec<Val>) -> Result<Val, InkErr> {
     };
 }
 
-pub fn builtin_string(args: Vec<Val>) -> Result<Val, InkErr> {
+pub fn builtin_string(_ctx: &mut dyn VmContext, args: Vec<Val>) -> Result<Val, InkErr> {
     if args.len() < 1 {
         return Err(InkErr::NotEnoughArguments);
     }
@@ -290,7 +307,7 @@ pub fn builtin_string(args: Vec<Val>) -> Result<Val, InkErr> {
     return Ok(Val::Str(ink_str_bytes));
 }
 
-pub fn builtin_len(args: Vec<Val>) -> Result<Val, InkErr> {
+pub fn builtin_len(_ctx: &mut dyn VmContext, args: Vec<Val>) -> Result<Val, InkErr> {
     if args.len() < 1 {
         return Err(InkErr::NotEnoughArguments);
     }
@@ -305,7 +322,7 @@ pub fn builtin_len(args: Vec<Val>) -> Result<Val, InkErr> {
     return Ok(Val::Number(length as f64));
 }
 
-pub fn builtin_load(args: Vec<Val>) -> Result<Val, InkErr> {
+pub fn builtin_load(_ctx: &mut dyn VmContext, args: Vec<Val>) -> Result<Val, InkErr> {
     if args.len() < 1 {
         return Err(InkErr::NotEnoughArguments);
     }
@@ -342,3 +359,44 @@ pub fn builtin_load(args: Vec<Val>) -> Result<Val, InkErr> {
         _ => Err(InkErr::InvalidArguments),
     };
 }
+
+// Without an event loop, wait() blocks for the given number of seconds and then calls back into
+// the Ink callback synchronously, before returning.
+pub fn builtin_wait(ctx: &mut dyn VmContext, args: Vec<Val>) -> Result<Val, InkErr> {
+    if args.len() < 2 {
+        return Err(InkErr::NotEnoughArguments);
+    }
+
+    let secs = match &args[0] {
+        Val::Number(n) if *n > 0.0 => *n,
+        Val::Number(_) => 0.0,
+        _ => return Err(InkErr::InvalidArguments),
+    };
+    match Duration::try_from_secs_f64(secs) {
+        Ok(duration) => thread::sleep(duration),
+        Err(_) => return Err(InkErr::InvalidArguments),
+    }
+
+    ctx.call(&args[1], vec![])?;
+    return Ok(Val::Null);
+}
+
+pub fn builtin_json_serialize(_ctx: &mut dyn VmContext, args: Vec<Val>) -> Result<Val, InkErr> {
+    if args.len() < 1 {
+        return Err(InkErr::NotEnoughArguments);
+    }
+
+    return Ok(Val::Str(json::serialize(&args[0])));
+}
+
+// jsonParse returns () on malformed input, like json.ink's de().
+pub fn builtin_json_parse(_ctx: &mut dyn VmContext, args: Vec<Val>) -> Result<Val, InkErr> {
+    if args.len() < 1 {
+        return Err(InkErr::NotEnoughArguments);
+    }
+
+    return match &args[0] {
+        Val::Str(s) => Ok(json::parse(s).unwrap_or(Val::Null)),
+        _ => Err(InkErr::InvalidArguments),
+    };
+}
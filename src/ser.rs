@@ -0,0 +1,764 @@
+// serde support for Ink values, enabled with the "serde" feature.
+//
+// to_val maps any Serialize type onto Ink values, and from_val reads any
+// Deserialize type back out of them. Structs and maps become composites
+// keyed by field name, sequences become list composites keyed by index, and
+// all numbers become Ink numbers. Keys follow Comp::set's conventions, so a
+// map keyed by 1 and a map keyed by '1' are indistinguishable.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use serde::Deserialize;
+
+use crate::comp::Comp;
+use crate::err::InkErr;
+use crate::val::Val;
+
+impl ser::Error for InkErr {
+    fn custom<T: fmt::Display>(msg: T) -> InkErr {
+        return InkErr::SerdeError(msg.to_string());
+    }
+}
+
+impl de::Error for InkErr {
+    fn custom<T: fmt::Display>(msg: T) -> InkErr {
+        return InkErr::SerdeError(msg.to_string());
+    }
+}
+
+pub fn to_val<T: Serialize + ?Sized>(value: &T) -> Result<Val, InkErr> {
+    return value.serialize(Serializer);
+}
+
+pub fn from_val<'de, T: Deserialize<'de>>(val: Val) -> Result<T, InkErr> {
+    return T::deserialize(Deserializer { val });
+}
+
+fn comp_val(comp: Comp) -> Val {
+    return Val::Comp(Rc::new(RefCell::new(comp)));
+}
+
+// enum variants with data are represented as a composite with a single key,
+// the variant name, like serde's default externally tagged representation.
+fn variant_val(variant: &str, val: Val) -> Val {
+    let mut comp = Comp::new();
+    comp.set(&Val::Str(variant.as_bytes().to_vec()), val);
+    return comp_val(comp);
+}
+
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Val;
+    type Error = InkErr;
+
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = CompSerializer;
+    type SerializeStruct = CompSerializer;
+    type SerializeStructVariant = CompSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Val, InkErr> {
+        return Ok(Val::Bool(v));
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v as f64));
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v as f64));
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v as f64));
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v as f64));
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v as f64));
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v as f64));
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v as f64));
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v as f64));
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v as f64));
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Val, InkErr> {
+        return Ok(Val::Number(v));
+    }
+
+    fn serialize_char(self, v: char) -> Result<Val, InkErr> {
+        return Ok(Val::Str(v.to_string().into_bytes()));
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Val, InkErr> {
+        return Ok(Val::Str(v.as_bytes().to_vec()));
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Val, InkErr> {
+        return Ok(Val::Str(v.to_vec()));
+    }
+
+    fn serialize_none(self) -> Result<Val, InkErr> {
+        return Ok(Val::Null);
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Val, InkErr> {
+        return value.serialize(self);
+    }
+
+    fn serialize_unit(self) -> Result<Val, InkErr> {
+        return Ok(Val::Null);
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Val, InkErr> {
+        return Ok(Val::Null);
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Val, InkErr> {
+        return self.serialize_str(variant);
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Val, InkErr> {
+        return value.serialize(self);
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Val, InkErr> {
+        return Ok(variant_val(variant, to_val(value)?));
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqSerializer, InkErr> {
+        return Ok(SeqSerializer {
+            variant: None,
+            items: vec![],
+        });
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, InkErr> {
+        return self.serialize_seq(Some(len));
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, InkErr> {
+        return self.serialize_seq(Some(len));
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SeqSerializer, InkErr> {
+        return Ok(SeqSerializer {
+            variant: Some(variant),
+            items: vec![],
+        });
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<CompSerializer, InkErr> {
+        return Ok(CompSerializer {
+            variant: None,
+            comp: Comp::new(),
+            next_key: None,
+        });
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<CompSerializer, InkErr> {
+        return self.serialize_map(Some(len));
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<CompSerializer, InkErr> {
+        return Ok(CompSerializer {
+            variant: Some(variant),
+            comp: Comp::new(),
+            next_key: None,
+        });
+    }
+}
+
+pub struct SeqSerializer {
+    variant: Option<&'static str>,
+    items: Vec<Val>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), InkErr> {
+        self.items.push(to_val(value)?);
+        return Ok(());
+    }
+
+    fn finish(self) -> Result<Val, InkErr> {
+        let list = comp_val(Comp::from_list(self.items));
+        return match self.variant {
+            Some(variant) => Ok(variant_val(variant, list)),
+            None => Ok(list),
+        };
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Val;
+    type Error = InkErr;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), InkErr> {
+        return self.push(value);
+    }
+
+    fn end(self) -> Result<Val, InkErr> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Val;
+    type Error = InkErr;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), InkErr> {
+        return self.push(value);
+    }
+
+    fn end(self) -> Result<Val, InkErr> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Val;
+    type Error = InkErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), InkErr> {
+        return self.push(value);
+    }
+
+    fn end(self) -> Result<Val, InkErr> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Val;
+    type Error = InkErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), InkErr> {
+        return self.push(value);
+    }
+
+    fn end(self) -> Result<Val, InkErr> {
+        return self.finish();
+    }
+}
+
+pub struct CompSerializer {
+    variant: Option<&'static str>,
+    comp: Comp,
+    next_key: Option<Val>,
+}
+
+impl CompSerializer {
+    fn finish(self) -> Result<Val, InkErr> {
+        let comp = comp_val(self.comp);
+        return match self.variant {
+            Some(variant) => Ok(variant_val(variant, comp)),
+            None => Ok(comp),
+        };
+    }
+}
+
+impl ser::SerializeMap for CompSerializer {
+    type Ok = Val;
+    type Error = InkErr;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), InkErr> {
+        self.next_key = Some(to_val(key)?);
+        return Ok(());
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), InkErr> {
+        let key = match self.next_key.take() {
+            Some(key) => key,
+            None => return Err(InkErr::SerdeError("map value without a key".to_string())),
+        };
+        self.comp.set(&key, to_val(value)?);
+        return Ok(());
+    }
+
+    fn end(self) -> Result<Val, InkErr> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeStruct for CompSerializer {
+    type Ok = Val;
+    type Error = InkErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), InkErr> {
+        self.comp
+            .set(&Val::Str(key.as_bytes().to_vec()), to_val(value)?);
+        return Ok(());
+    }
+
+    fn end(self) -> Result<Val, InkErr> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeStructVariant for CompSerializer {
+    type Ok = Val;
+    type Error = InkErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), InkErr> {
+        self.comp
+            .set(&Val::Str(key.as_bytes().to_vec()), to_val(value)?);
+        return Ok(());
+    }
+
+    fn end(self) -> Result<Val, InkErr> {
+        return self.finish();
+    }
+}
+
+pub struct Deserializer {
+    val: Val,
+}
+
+impl Deserializer {
+    fn number(&self) -> Result<f64, InkErr> {
+        return match self.val {
+            Val::Number(n) => Ok(n),
+            _ => Err(InkErr::ExpectedNumber),
+        };
+    }
+
+    fn integer(&self) -> Result<i64, InkErr> {
+        let n = self.number()?;
+        if n.fract() != 0.0 {
+            return Err(InkErr::SerdeError(format!(
+                "expected an integer, found {}",
+                n
+            )));
+        }
+        return Ok(n as i64);
+    }
+
+    fn comp(&self) -> Result<Comp, InkErr> {
+        return match &self.val {
+            Val::Comp(comp_rc) => Ok(comp_rc.borrow().clone()),
+            _ => Err(InkErr::ExpectedCompositeValue),
+        };
+    }
+}
+
+macro_rules! deserialize_integer {
+    ($method:ident, $visit:ident, $ty:ty) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+            let n = self.integer()?;
+            return match <$ty>::try_from(n) {
+                Ok(n) => visitor.$visit(n),
+                Err(_) => Err(InkErr::SerdeError(format!(
+                    "{} is out of range for {}",
+                    n,
+                    stringify!($ty)
+                ))),
+            };
+        }
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = InkErr;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return match self.val {
+            Val::Empty | Val::Null => visitor.visit_unit(),
+            Val::Bool(b) => visitor.visit_bool(b),
+            Val::Number(n) => {
+                if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+                    visitor.visit_i64(n as i64)
+                } else {
+                    visitor.visit_f64(n)
+                }
+            }
+            Val::Str(s) => match String::from_utf8(s) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+            },
+            Val::Comp(_) => {
+                let comp = self.comp()?;
+                match comp.as_list() {
+                    Some(items) if !items.is_empty() => visitor.visit_seq(SeqAccess {
+                        items: items.into_iter(),
+                    }),
+                    _ => visitor.visit_map(MapAccess {
+                        entries: comp.map.into_iter(),
+                        next_val: None,
+                    }),
+                }
+            }
+            Val::Func(_, _) | Val::NativeFunc(_) | Val::Escaped(_) => Err(InkErr::SerdeError(
+                "cannot deserialize a function".to_string(),
+            )),
+        };
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return match self.val {
+            Val::Bool(b) => visitor.visit_bool(b),
+            _ => Err(InkErr::ExpectedBoolean),
+        };
+    }
+
+    deserialize_integer!(deserialize_i8, visit_i8, i8);
+    deserialize_integer!(deserialize_i16, visit_i16, i16);
+    deserialize_integer!(deserialize_i32, visit_i32, i32);
+    deserialize_integer!(deserialize_i64, visit_i64, i64);
+    deserialize_integer!(deserialize_u8, visit_u8, u8);
+    deserialize_integer!(deserialize_u16, visit_u16, u16);
+    deserialize_integer!(deserialize_u32, visit_u32, u32);
+    deserialize_integer!(deserialize_u64, visit_u64, u64);
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return visitor.visit_f32(self.number()? as f32);
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return visitor.visit_f64(self.number()?);
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return self.deserialize_string(visitor);
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return self.deserialize_string(visitor);
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return match self.val {
+            Val::Str(s) => match String::from_utf8(s) {
+                Ok(s) => visitor.visit_string(s),
+                Err(_) => Err(InkErr::ExpectedString),
+            },
+            _ => Err(InkErr::ExpectedString),
+        };
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return self.deserialize_byte_buf(visitor);
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return match self.val {
+            Val::Str(s) => visitor.visit_byte_buf(s),
+            _ => Err(InkErr::ExpectedString),
+        };
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return match self.val {
+            Val::Empty | Val::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        };
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return match self.val {
+            Val::Empty | Val::Null => visitor.visit_unit(),
+            _ => Err(InkErr::SerdeError("expected ()".to_string())),
+        };
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, InkErr> {
+        return self.deserialize_unit(visitor);
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, InkErr> {
+        return visitor.visit_newtype_struct(self);
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return match self.comp()?.as_list() {
+            Some(items) => visitor.visit_seq(SeqAccess {
+                items: items.into_iter(),
+            }),
+            None => Err(InkErr::ExpectedList),
+        };
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, InkErr> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, InkErr> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return visitor.visit_map(MapAccess {
+            entries: self.comp()?.map.into_iter(),
+            next_val: None,
+        });
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, InkErr> {
+        return self.deserialize_map(visitor);
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, InkErr> {
+        return match self.val {
+            Val::Str(_) => {
+                let variant: String = from_val(self.val)?;
+                visitor.visit_enum(variant.into_deserializer())
+            }
+            Val::Comp(_) => {
+                let mut entries = self.comp()?.map.into_iter();
+                match (entries.next(), entries.next()) {
+                    (Some((variant, val)), None) => visitor.visit_enum(EnumAccess { variant, val }),
+                    _ => Err(InkErr::SerdeError(
+                        "expected a composite with a single variant key".to_string(),
+                    )),
+                }
+            }
+            _ => Err(InkErr::SerdeError("expected an enum variant".to_string())),
+        };
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return self.deserialize_string(visitor);
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return visitor.visit_unit();
+    }
+}
+
+struct SeqAccess {
+    items: std::vec::IntoIter<Val>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = InkErr;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, InkErr> {
+        return match self.items.next() {
+            Some(val) => seed.deserialize(Deserializer { val }).map(Some),
+            None => Ok(None),
+        };
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        return Some(self.items.len());
+    }
+}
+
+struct MapAccess {
+    entries: std::collections::hash_map::IntoIter<String, Val>,
+    next_val: Option<Val>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = InkErr;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, InkErr> {
+        return match self.entries.next() {
+            Some((key, val)) => {
+                self.next_val = Some(val);
+                seed.deserialize(KeyDeserializer { key }).map(Some)
+            }
+            None => Ok(None),
+        };
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, InkErr> {
+        return match self.next_val.take() {
+            Some(val) => seed.deserialize(Deserializer { val }),
+            None => Err(InkErr::SerdeError("map key without a value".to_string())),
+        };
+    }
+}
+
+// KeyDeserializer reads a composite's key, which is always a string. Maps
+// keyed by numbers or booleans were serialized with those keys written out,
+// so keys are parsed back into them when that's what's expected.
+struct KeyDeserializer {
+    key: String,
+}
+
+macro_rules! deserialize_key {
+    ($method:ident, $visit:ident, $ty:ty) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+            return match self.key.parse::<$ty>() {
+                Ok(key) => visitor.$visit(key),
+                Err(_) => Err(InkErr::SerdeError(format!(
+                    "expected a key of type {}, found {}",
+                    stringify!($ty),
+                    self.key
+                ))),
+            };
+        }
+    };
+}
+
+impl<'de> de::Deserializer<'de> for KeyDeserializer {
+    type Error = InkErr;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, InkErr> {
+        return visitor.visit_string(self.key);
+    }
+
+    deserialize_key!(deserialize_bool, visit_bool, bool);
+    deserialize_key!(deserialize_i8, visit_i8, i8);
+    deserialize_key!(deserialize_i16, visit_i16, i16);
+    deserialize_key!(deserialize_i32, visit_i32, i32);
+    deserialize_key!(deserialize_i64, visit_i64, i64);
+    deserialize_key!(deserialize_u8, visit_u8, u8);
+    deserialize_key!(deserialize_u16, visit_u16, u16);
+    deserialize_key!(deserialize_u32, visit_u32, u32);
+    deserialize_key!(deserialize_u64, visit_u64, u64);
+    deserialize_key!(deserialize_f32, visit_f32, f32);
+    deserialize_key!(deserialize_f64, visit_f64, f64);
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, InkErr> {
+        let key: de::value::StringDeserializer<InkErr> = self.key.into_deserializer();
+        return key.deserialize_enum(name, variants, visitor);
+    }
+
+    serde::forward_to_deserialize_any! {
+        char str string bytes byte_buf option unit unit_struct newtype_struct seq
+        tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct EnumAccess {
+    variant: String,
+    val: Val,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = InkErr;
+    type Variant = Deserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Deserializer), InkErr> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        return Ok((variant, Deserializer { val: self.val }));
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = InkErr;
+
+    fn unit_variant(self) -> Result<(), InkErr> {
+        return Ok(());
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, InkErr> {
+        return seed.deserialize(self);
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, InkErr> {
+        return de::Deserializer::deserialize_seq(self, visitor);
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, InkErr> {
+        return de::Deserializer::deserialize_map(self, visitor);
+    }
+}
@@ -0,0 +1,156 @@
+// Source positions. Tokens, AST nodes and compiled instructions record where
+// they came from as byte offsets into the source, and a SourceFile turns those
+// offsets into lines and columns. The start of every line is found once, when
+// the file is added, so each lookup is a binary search rather than a scan of
+// the source.
+//
+// A SourceMap holds every file of a program, like the main program and the
+// files it loads. Each file is given a base, and the positions of a file start
+// at its base, so that a single offset identifies both a file and a place in
+// it. The first file has base 0, so its positions are plain byte offsets.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub struct SourceFile {
+    name: String,
+    text: String,
+    base: usize,
+    // byte offsets of the start of each line, relative to the file
+    line_starts: Vec<usize>,
+}
+
+// Position is a 1-based line and column in a file. Columns count bytes, so a
+// position takes the same time to find whatever the line holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+impl SourceFile {
+    pub fn new(name: &str, text: &str) -> SourceFile {
+        return SourceFile::with_base(name, text, 0);
+    }
+
+    fn with_base(name: &str, text: &str, base: usize) -> SourceFile {
+        let mut line_starts = vec![0];
+        for (i, b) in text.bytes().enumerate() {
+            if b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+
+        return SourceFile {
+            name: name.to_string(),
+            text: text.to_string(),
+            base,
+            line_starts,
+        };
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub fn text(&self) -> &str {
+        return &self.text;
+    }
+
+    pub fn base(&self) -> usize {
+        return self.base;
+    }
+
+    // line returns the 1-based line of a byte offset into the file
+    pub fn line(&self, offset: usize) -> usize {
+        return match self.line_starts.binary_search(&offset) {
+            Ok(idx) => idx + 1,
+            Err(idx) => idx,
+        };
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let line = self.line(offset);
+        return Position {
+            line,
+            col: offset - self.line_starts[line - 1] + 1,
+        };
+    }
+
+    // line_start returns the byte offset of the start of a 1-based line
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        return self.line_starts.get(line.wrapping_sub(1)).copied();
+    }
+
+    // line_count counts the lines of the file, including an empty last line
+    // after a trailing newline
+    pub fn line_count(&self) -> usize {
+        return self.line_starts.len();
+    }
+
+    // line_text returns the text of a 1-based line, without its newline
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = match self.line_start(line) {
+            Some(start) => start,
+            None => return "",
+        };
+        let end = match self.line_start(line + 1) {
+            Some(next) => next - 1,
+            None => self.text.len(),
+        };
+        return self.text[start..end].trim_end_matches('\r');
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        return SourceMap { files: vec![] };
+    }
+
+    // add adds a file to the map and returns its base. Files are laid out one
+    // after another, with a gap of one so that the offset just past the end of
+    // a file, where errors at the end of input point, still belongs to it.
+    pub fn add(&mut self, name: &str, text: &str) -> usize {
+        let base = match self.files.last() {
+            Some(file) => file.base + file.text.len() + 1,
+            None => 0,
+        };
+        self.files.push(SourceFile::with_base(name, text, base));
+        return base;
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        return &self.files;
+    }
+
+    // file returns the file a position is in
+    pub fn file(&self, pos: usize) -> Option<&SourceFile> {
+        let idx = match self.files.binary_search_by_key(&pos, |file| file.base) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        let file = &self.files[idx];
+        if pos > file.base + file.text.len() {
+            return None;
+        }
+        return Some(file);
+    }
+
+    // position returns the file a position is in, and its line and column there
+    pub fn position(&self, pos: usize) -> Option<(&SourceFile, Position)> {
+        let file = self.file(pos)?;
+        return Some((file, file.position(pos - file.base)));
+    }
+}
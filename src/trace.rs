@@ -0,0 +1,130 @@
+// Execution tracer for the VM. When tracing is enabled, the VM logs every
+// instruction it executes with its block, instruction pointer and the value
+// left in its destination register, either as indented text or as JSON lines
+// for other tools to consume.
+//
+// Instructions are logged once they finish executing. A call into an Ink
+// function finishes only when the callee returns, so calls are logged as they
+// are made, without a value, and the callee's instructions follow.
+
+use std::fmt;
+use std::io::Write;
+
+use crate::gen::Inst;
+use crate::json;
+use crate::val::Val;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+pub struct TraceEvent<'e> {
+    pub block_idx: usize,
+    pub block_name: &'e str,
+    pub ip: usize,
+    pub depth: usize,
+    pub inst: &'e Inst,
+    // result is None for calls that have not returned yet
+    pub result: Option<&'e Val>,
+}
+
+pub struct Tracer {
+    format: TraceFormat,
+    out: Box<dyn Write>,
+
+    // if any filters are given, only instructions in blocks with one of these
+    // indexes or function names are traced
+    blocks: Vec<usize>,
+    names: Vec<String>,
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Tracer({:?}, blocks: {:?}, names: {:?})",
+            self.format, self.blocks, self.names
+        )
+    }
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Tracer {
+        return Tracer {
+            format,
+            out,
+            blocks: vec![],
+            names: vec![],
+        };
+    }
+
+    pub fn filter_block(&mut self, block_idx: usize) {
+        self.blocks.push(block_idx);
+    }
+
+    pub fn filter_name(&mut self, name: String) {
+        self.names.push(name);
+    }
+
+    pub fn traces(&self, block_idx: usize, block_name: &str) -> bool {
+        if self.blocks.is_empty() && self.names.is_empty() {
+            return true;
+        }
+        return self.blocks.contains(&block_idx)
+            || self.names.iter().any(|name| name == block_name);
+    }
+
+    // trace output is best-effort, so write errors (like a closed pipe) are
+    // ignored rather than interrupting the program being traced.
+    pub fn record(&mut self, event: &TraceEvent) {
+        let _ = match self.format {
+            TraceFormat::Text => self.record_text(event),
+            TraceFormat::Json => self.record_json(event),
+        };
+    }
+
+    fn record_text(&mut self, event: &TraceEvent) -> std::io::Result<()> {
+        let indent = "  ".repeat(event.depth.saturating_sub(1));
+        let result = match event.result {
+            Some(val) => format!("=> {}", val),
+            None => "=> (call)".to_string(),
+        };
+        if event.block_name.is_empty() {
+            write!(self.out, "{}#{}", indent, event.block_idx)?;
+        } else {
+            write!(
+                self.out,
+                "{}{}#{}",
+                indent, event.block_name, event.block_idx
+            )?;
+        }
+        return writeln!(self.out, ":{}\t{}\t{}", event.ip, event.inst, result);
+    }
+
+    fn record_json(&mut self, event: &TraceEvent) -> std::io::Result<()> {
+        let json_str = |s: String| {
+            String::from_utf8_lossy(&json::serialize(&Val::Str(s.into_bytes()))).into_owned()
+        };
+        let result = match event.result {
+            Some(val) => json_str(val.to_string()),
+            None => "null".to_string(),
+        };
+        return writeln!(
+            self.out,
+            "{{\"block\":{},\"fn\":{},\"ip\":{},\"depth\":{},\"dest\":{},\"inst\":{},\"val\":{}}}",
+            event.block_idx,
+            json_str(event.block_name.to_string()),
+            event.ip,
+            event.depth,
+            event.inst.dest,
+            json_str(event.inst.op.to_string()),
+            result
+        );
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
@@ -4,8 +4,42 @@ use std::rc::Rc;
 
 use crate::comp::Comp;
 use crate::err::InkErr;
+use crate::vm::VmContext;
 
-pub type NativeFn = fn(Vec<Val>) -> Result<Val, InkErr>;
+// NativeFn is a host-defined function callable from Ink. Because the implementation is a boxed
+// closure, native functions may capture host state (handles, counters, loggers), and they receive
+// a VmContext through which they can call back into Ink functions synchronously.
+//
+// The name is used for debugging output, and to refer to builtins symbolically.
+pub type NativeFnBody = dyn Fn(&mut dyn VmContext, Vec<Val>) -> Result<Val, InkErr>;
+
+#[derive(Clone)]
+pub struct NativeFn {
+    pub name: String,
+    pub func: Rc<NativeFnBody>,
+}
+
+impl NativeFn {
+    pub fn new<F>(name: &str, func: F) -> NativeFn
+    where
+        F: Fn(&mut dyn VmContext, Vec<Val>) -> Result<Val, InkErr> + 'static,
+    {
+        return NativeFn {
+            name: name.to_string(),
+            func: Rc::new(func),
+        };
+    }
+
+    pub fn call(&self, ctx: &mut dyn VmContext, args: Vec<Val>) -> Result<Val, InkErr> {
+        return (self.func)(ctx, args);
+    }
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFn({})", self.name)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum Val {
@@ -57,7 +91,23 @@ pub enum Val {
 impl fmt::Display for Val {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Val::Str(_) => write!(f, "\'{}\'", self.to_ink_string().replace("'", "\\'")),
+            // strings are written as literals that lex back to the same bytes
+            Val::Str(s) => {
+                write!(f, "'")?;
+                for chunk in s.utf8_chunks() {
+                    for c in chunk.valid().chars() {
+                        match c {
+                            '\\' => write!(f, "\\\\")?,
+                            '\'' => write!(f, "\\'")?,
+                            _ => write!(f, "{}", c)?,
+                        }
+                    }
+                    for b in chunk.invalid() {
+                        write!(f, "\\x{:02x}", b)?;
+                    }
+                }
+                write!(f, "'")
+            }
             _ => write!(f, "{}", self.to_ink_string()),
         }
     }
@@ -107,6 +157,8 @@ impl Val {
     pub fn to_ink_string(&self) -> String {
         match self {
             Val::Empty => "_".to_string(),
+            // negative zero prints as 0, as in Ink
+            Val::Number(n) if *n == 0.0 => "0".to_string(),
             Val::Number(n) => format!("{}", n),
             Val::Str(a) => String::from_utf8_lossy(a).into_owned(),
             Val::Bool(a) => {
@@ -189,7 +241,7 @@ pub fn set_on_bytestring(s: &mut Vec<u8>, key: &Val, val: Val) -> Result<(), Ink
 
 pub fn get_from_bytestring(s: &Vec<u8>, key: &Val) -> Result<Val, InkErr> {
     let index = key.index_coerce()?;
-    if index > s.len() {
+    if index >= s.len() {
         return Ok(Val::Null);
     }
 
@@ -0,0 +1,172 @@
+// Bytecode verifier. The VM indexes registers, constants, binds and blocks
+// without bounds checks, so a malformed program (from a buggy compiler or
+// optimizer pass, or a corrupt bytecode file) would panic mid-execution.
+// verify proves ahead of time that every such index is in range.
+
+use crate::err::InkErr;
+use crate::gen::{Block, Op, Reg};
+use crate::val::Val;
+
+pub fn verify(prog: &[Block]) -> Result<(), InkErr> {
+    if prog.is_empty() {
+        return Err(InkErr::VerificationFailed(
+            "program has no main block".to_string(),
+        ));
+    }
+
+    for (block_idx, block) in prog.iter().enumerate() {
+        verify_block(prog, block_idx, block)?;
+    }
+    return Ok(());
+}
+
+fn fail(block_idx: usize, ip: usize, msg: String) -> Result<(), InkErr> {
+    return Err(InkErr::VerificationFailed(format!(
+        "#{}, instruction {}: {}",
+        block_idx, ip, msg
+    )));
+}
+
+fn verify_block(prog: &[Block], block_idx: usize, block: &Block) -> Result<(), InkErr> {
+    // the VM reads the return value from the last instruction's dest,
+    // so every block needs at least one instruction.
+    if block.code.is_empty() {
+        return fail(block_idx, 0, "block has no instructions".to_string());
+    }
+    if block.binds_names.len() > block.binds.len() {
+        return fail(
+            block_idx,
+            0,
+            format!(
+                "{} bind names for {} binds",
+                block.binds_names.len(),
+                block.binds.len()
+            ),
+        );
+    }
+
+    for (const_idx, val) in block.consts.iter().enumerate() {
+        if let Val::Func(callee_idx, heap_vals) = val {
+            if *callee_idx >= prog.len() {
+                return fail(
+                    block_idx,
+                    0,
+                    format!(
+                        "const {} refers to missing block #{}",
+                        const_idx, callee_idx
+                    ),
+                );
+            }
+            // LOAD_CONST appends the callee's binds to the constant's heap values, so any
+            // values already in the constant would overflow the callee frame's binds.
+            if !heap_vals.is_empty() {
+                return fail(
+                    block_idx,
+                    0,
+                    format!("function const {} has preset heap values", const_idx),
+                );
+            }
+        }
+    }
+
+    let check_reg = |ip: usize, reg: Reg| -> Result<(), InkErr> {
+        if reg >= block.slots {
+            return fail(
+                block_idx,
+                ip,
+                format!("register @{} out of range of {} slots", reg, block.slots),
+            );
+        }
+        return Ok(());
+    };
+
+    for (ip, inst) in block.code.iter().enumerate() {
+        check_reg(ip, inst.dest)?;
+
+        match &inst.op {
+            Op::Nop | Op::MakeComp => (),
+            Op::Mov(reg) | Op::Escape(reg) | Op::Neg(reg) => check_reg(ip, *reg)?,
+            Op::LoadConst(idx) => {
+                let val = match block.consts.get(*idx) {
+                    Some(val) => val,
+                    None => {
+                        return fail(
+                            block_idx,
+                            ip,
+                            format!(
+                                "const {} out of range of {} consts",
+                                idx,
+                                block.consts.len()
+                            ),
+                        )
+                    }
+                };
+
+                // loading a closure reads the callee's binds from this frame's registers
+                if let Val::Func(callee_idx, _) = val {
+                    for reg in prog[*callee_idx].binds.iter() {
+                        check_reg(ip, *reg)?;
+                    }
+                }
+            }
+            Op::LoadEsc(idx) => {
+                if *idx >= block.binds.len() {
+                    return fail(
+                        block_idx,
+                        ip,
+                        format!("bind {} out of range of {} binds", idx, block.binds.len()),
+                    );
+                }
+            }
+            Op::Call(reg, args) => {
+                check_reg(ip, *reg)?;
+                for arg in args.iter() {
+                    check_reg(ip, *arg)?;
+                }
+            }
+            Op::CallIfEq(reg, a, b, skip) => {
+                check_reg(ip, *reg)?;
+                check_reg(ip, *a)?;
+                check_reg(ip, *b)?;
+
+                // a taken branch skips the next `skip` branches in this block
+                let later_branches = block.code[ip + 1..]
+                    .iter()
+                    .filter(|inst| matches!(inst.op, Op::CallIfEq(_, _, _, _)))
+                    .count();
+                if *skip > later_branches {
+                    return fail(
+                        block_idx,
+                        ip,
+                        format!(
+                            "branch skips {} branches, but only {} follow",
+                            skip, later_branches
+                        ),
+                    );
+                }
+            }
+            Op::SetComp(reg, k, v) => {
+                check_reg(ip, *reg)?;
+                check_reg(ip, *k)?;
+                check_reg(ip, *v)?;
+            }
+            Op::GetComp(a, b)
+            | Op::Add(a, b)
+            | Op::Sub(a, b)
+            | Op::Mul(a, b)
+            | Op::Div(a, b)
+            | Op::Mod(a, b)
+            | Op::Gtr(a, b)
+            | Op::Lss(a, b)
+            | Op::Eql(a, b)
+            | Op::And(a, b)
+            | Op::Or(a, b)
+            | Op::Xor(a, b) => {
+                check_reg(ip, *a)?;
+                check_reg(ip, *b)?;
+            }
+        }
+    }
+
+    return Ok(());
+}
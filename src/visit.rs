@@ -0,0 +1,124 @@
+// Traversal of syntax trees. A pass over the tree implements Visitor, or
+// VisitorMut if it changes the tree, and overrides `visit` for the nodes it
+// cares about. Calling `walk` or `walk_mut` from there visits the children of
+// a node, so every pass reaches every subtree the same way, without its own
+// copy of the shape of each kind of node.
+//
+// Children are visited in the order they appear in the source. That includes
+// names that are not evaluated, like the arguments of a function literal and
+// `b` in `a.b` and `{b: c}`, so passes that care about the difference check
+// for them in the parent.
+
+use crate::err::InkErr;
+use crate::parse::Node;
+
+pub trait Visitor {
+    fn visit(&mut self, node: &Node) {
+        walk(self, node);
+    }
+}
+
+// walk visits each child of a node
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, node: &Node) {
+    match node {
+        Node::UnaryExpr { op: _, arg } => visitor.visit(arg),
+        Node::BinaryExpr { op: _, left, right } => {
+            visitor.visit(left);
+            visitor.visit(right);
+        }
+        Node::FnCall { func, args } => {
+            visitor.visit(func);
+            for arg in args.iter() {
+                visitor.visit(arg);
+            }
+        }
+        Node::MatchClause { target, expr } => {
+            visitor.visit(target);
+            visitor.visit(expr);
+        }
+        Node::MatchExpr { cond, clauses } => {
+            visitor.visit(cond);
+            for clause in clauses.iter() {
+                visitor.visit(clause);
+            }
+        }
+        Node::ExprList(nodes) | Node::ObjectLiteral(nodes) | Node::ListLiteral(nodes) => {
+            for node in nodes.iter() {
+                visitor.visit(node);
+            }
+        }
+        Node::ObjectEntry { key, val } => {
+            visitor.visit(key);
+            visitor.visit(val);
+        }
+        Node::FnLiteral { args, body } => {
+            for arg in args.iter() {
+                visitor.visit(arg);
+            }
+            visitor.visit(body);
+        }
+        Node::Trivia { node, .. } => visitor.visit(node),
+        Node::EmptyIdent
+        | Node::Ident(_, _, _)
+        | Node::NumberLiteral(_, _)
+        | Node::StringLiteral(_, _)
+        | Node::BooleanLiteral(_, _) => (),
+    }
+}
+
+// VisitorMut is Visitor for passes that change the tree in place. A pass may
+// fail, and the first error stops the traversal.
+pub trait VisitorMut {
+    fn visit_mut(&mut self, node: &mut Node) -> Result<(), InkErr> {
+        return walk_mut(self, node);
+    }
+}
+
+// walk_mut visits each child of a node, and may change any of them
+pub fn walk_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Node) -> Result<(), InkErr> {
+    match node {
+        Node::UnaryExpr { op: _, arg } => visitor.visit_mut(arg)?,
+        Node::BinaryExpr { op: _, left, right } => {
+            visitor.visit_mut(left)?;
+            visitor.visit_mut(right)?;
+        }
+        Node::FnCall { func, args } => {
+            visitor.visit_mut(func)?;
+            for arg in args.iter_mut() {
+                visitor.visit_mut(arg)?;
+            }
+        }
+        Node::MatchClause { target, expr } => {
+            visitor.visit_mut(target)?;
+            visitor.visit_mut(expr)?;
+        }
+        Node::MatchExpr { cond, clauses } => {
+            visitor.visit_mut(cond)?;
+            for clause in clauses.iter_mut() {
+                visitor.visit_mut(clause)?;
+            }
+        }
+        Node::ExprList(nodes) | Node::ObjectLiteral(nodes) | Node::ListLiteral(nodes) => {
+            for node in nodes.iter_mut() {
+                visitor.visit_mut(node)?;
+            }
+        }
+        Node::ObjectEntry { key, val } => {
+            visitor.visit_mut(key)?;
+            visitor.visit_mut(val)?;
+        }
+        Node::FnLiteral { args, body } => {
+            for arg in args.iter_mut() {
+                visitor.visit_mut(arg)?;
+            }
+            visitor.visit_mut(body)?;
+        }
+        Node::Trivia { node, .. } => visitor.visit_mut(node)?,
+        Node::EmptyIdent
+        | Node::Ident(_, _, _)
+        | Node::NumberLiteral(_, _)
+        | Node::StringLiteral(_, _)
+        | Node::BooleanLiteral(_, _) => (),
+    }
+    return Ok(());
+}
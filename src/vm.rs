@@ -4,32 +4,97 @@ use std::mem;
 use std::rc::Rc;
 
 use crate::comp::Comp;
+use crate::coverage::Coverage;
 use crate::err::InkErr;
 use crate::gen::{Block, Op, Reg};
+use crate::profile::{self, Profiler, Site};
 use crate::runtime;
-use crate::val::Val;
+use crate::trace::{TraceEvent, Tracer};
+use crate::val::{NativeFn, Val};
 
 const MAX_STACK_FRAMES: usize = 10000;
 
+// VmContext is the view of a running VM given to native functions. It lets host-defined
+// functions call back into Ink functions synchronously.
+pub trait VmContext {
+    fn call(&mut self, func: &Val, args: Vec<Val>) -> Result<Val, InkErr>;
+}
+
+// DebugHook is called before the VM executes each instruction, and may inspect the VM and
+// call into it. Debuggers pause a program either by not returning until it should resume, or
+// by returning true, which suspends the VM and returns Status::Paused from Vm::resume.
+//
+// The VM can't be suspended while a native function is calling back into Ink, so a pause
+// requested there takes effect once the native function returns.
+pub trait DebugHook {
+    fn before_inst(&mut self, vm: &mut Vm) -> bool;
+}
+
+impl fmt::Debug for dyn DebugHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DebugHook")
+    }
+}
+
 #[derive(Debug)]
 pub struct Frame {
+    id: usize,
     ip: usize, // instruction pointer
     rp: Reg,   // return register
     regs: Vec<Val>,
     binds: Vec<Val>,
+    block_idx: usize,
     block: Block,
+
+    // call stack node in the profiler, if profiling
+    profile_node: usize,
 }
 
 impl Frame {
-    fn new(rp: Reg, block: Block) -> Frame {
+    fn new(rp: Reg, block_idx: usize, block: Block) -> Frame {
         return Frame {
+            id: 0,
             ip: 0,
             rp,
             regs: vec![Val::Empty; block.slots],
             binds: vec![Val::Empty; block.binds.len()],
+            block_idx,
             block,
+            profile_node: profile::ROOT,
         };
     }
+
+    // id uniquely identifies a frame within a run, even across tail calls that reuse
+    // the same stack depth.
+    pub fn id(&self) -> usize {
+        return self.id;
+    }
+
+    pub fn ip(&self) -> usize {
+        return self.ip;
+    }
+
+    pub fn block_idx(&self) -> usize {
+        return self.block_idx;
+    }
+
+    pub fn block(&self) -> &Block {
+        return &self.block;
+    }
+
+    pub fn regs(&self) -> &[Val] {
+        return &self.regs;
+    }
+
+    pub fn binds(&self) -> &[Val] {
+        return &self.binds;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Status {
+    Paused,
+    Exited(Val),
 }
 
 #[derive(Debug)]
@@ -37,6 +102,19 @@ pub struct Vm {
     heap: Vec<Val>, // escaped (bind) values
     stack: Vec<Frame>,
     prog: Vec<Block>,
+    profiler: Option<Profiler>,
+    tracer: Option<Tracer>,
+    coverage: Option<Coverage>,
+    // number of instructions left to run, if limited
+    budget: Option<u64>,
+    debug_hook: Option<Box<dyn DebugHook>>,
+    frame_count: usize,
+
+    // a debug hook asked to pause, and the VM has not suspended yet
+    pause_pending: bool,
+    // the VM has just been resumed, so the next instruction has already been seen by the
+    // debug hook
+    resuming: bool,
 }
 
 impl fmt::Display for Vm {
@@ -79,15 +157,117 @@ impl Vm {
             heap: Vec::<Val>::new(),
             stack: Vec::<Frame>::new(),
             prog,
+            profiler: None,
+            tracer: None,
+            coverage: None,
+            budget: None,
+            debug_hook: None,
+            frame_count: 0,
+            pause_pending: false,
+            resuming: false,
+        };
+    }
+
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::new(&self.prog));
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        return self.profiler.as_ref();
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new(&self.prog));
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        return self.coverage.as_ref();
+    }
+
+    // set_budget limits the number of instructions the VM runs, after which it
+    // stops with an error, for running untrusted programs
+    pub fn set_budget(&mut self, insts: u64) {
+        self.budget = Some(insts);
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn set_debug_hook(&mut self, hook: Box<dyn DebugHook>) {
+        self.debug_hook = Some(hook);
+    }
+
+    pub fn take_debug_hook(&mut self) -> Option<Box<dyn DebugHook>> {
+        return self.debug_hook.take();
+    }
+
+    pub fn stack(&self) -> &[Frame] {
+        return &self.stack;
+    }
+
+    pub fn heap(&self) -> &[Val] {
+        return &self.heap;
+    }
+
+    pub fn prog(&self) -> &[Block] {
+        return &self.prog;
+    }
+
+    // resolve returns the value behind a register, following it to the heap if it has
+    // escaped.
+    pub fn resolve<'v>(&'v self, val: &'v Val) -> &'v Val {
+        return val.or_from_heap(&self.heap);
+    }
+
+    // link appends a separately compiled program to this VM's program, so it can be
+    // called with access to this VM's values, and returns the index of its main block.
+    pub fn link(&mut self, blocks: Vec<Block>) -> usize {
+        let offset = self.prog.len();
+        for mut block in blocks {
+            for val in block.consts.iter_mut() {
+                if let Val::Func(block_idx, _) = val {
+                    *block_idx += offset;
+                }
+            }
+            self.prog.push(block);
+        }
+        return offset;
+    }
+
+    fn push_frame(&mut self, mut frame: Frame) {
+        frame.id = self.frame_count;
+        self.frame_count += 1;
+        self.stack.push(frame);
+    }
+
+    // profile_call returns the profiler node for calling `site` from the call stack at
+    // `parent`, and makes it the current stack.
+    fn profile_call(&mut self, parent: usize, site: Site) -> usize {
+        return match &mut self.profiler {
+            Some(profiler) => {
+                let node = profiler.child(parent, site);
+                profiler.enter(node);
+                node
+            }
+            None => profile::ROOT,
         };
     }
 
-    fn is_running(&self) -> bool {
-        return self.stack.len() > 0;
+    fn profile_enter(&mut self, node: usize) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(node);
+        }
+    }
+
+    // base is the stack depth at which the current (possibly nested) execution began.
+    // Frames at or below base belong to a caller of execute() and must not be popped.
+    fn is_running(&self, base: usize) -> bool {
+        return self.stack.len() > base;
     }
 
-    fn should_pop_frame(&self) -> bool {
-        if self.stack.len() == 0 {
+    fn should_pop_frame(&self, base: usize) -> bool {
+        if self.stack.len() <= base {
             return false;
         }
 
@@ -95,24 +275,88 @@ impl Vm {
         return frame.ip == frame.block.code.len();
     }
 
+    // run runs the program to completion, resuming it whenever a debug hook pauses it.
     pub fn run(&mut self) -> Result<Val, InkErr> {
+        self.start();
+        loop {
+            if let Status::Exited(val) = self.resume()? {
+                return Ok(val);
+            }
+        }
+    }
+
+    // start sets up the main block to run, without running any of it. The program then runs
+    // with resume.
+    pub fn start(&mut self) {
         let main_block = &self.prog.first().unwrap();
-        let main_frame = Frame::new(0, (*main_block).clone());
-        self.stack.push(main_frame);
+        let mut main_frame = Frame::new(0, 0, (*main_block).clone());
+        main_frame.profile_node = self.profile_call(profile::ROOT, Site::Block(0));
+        self.push_frame(main_frame);
+    }
+
+    // resume runs a started program until it exits, or until a debug hook pauses it. While
+    // the program is paused, the VM can be inspected and called into.
+    pub fn resume(&mut self) -> Result<Status, InkErr> {
+        let result = self.execute(0);
+        if let Ok(Status::Paused) = result {
+            return result;
+        }
+
+        // attribute the remaining time to the last running stack
+        self.profile_enter(profile::ROOT);
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush();
+        }
+        return result;
+    }
 
+    // execute runs the VM until the stack unwinds back down to depth `base`, and returns the
+    // return value of the frame that was sitting just above `base`. Only the outermost
+    // execution, at base 0, is ever paused.
+    fn execute(&mut self, base: usize) -> Result<Status, InkErr> {
         let mut maybe_callee_frame: Option<Frame>;
+        let mut maybe_native_call: Option<(NativeFn, Vec<Val>)>;
 
-        while self.is_running() {
+        while self.is_running(base) {
             maybe_callee_frame = None;
+            maybe_native_call = None;
 
             // artificial stack overflow limit
             if self.stack.len() == MAX_STACK_FRAMES {
-                eprintln!("Stack limit {} exceeded.", MAX_STACK_FRAMES);
-                std::process::exit(2);
+                return Err(InkErr::StackOverflow(MAX_STACK_FRAMES));
+            }
+
+            let resumed = base == 0 && mem::take(&mut self.resuming);
+            if !resumed {
+                if let Some(mut hook) = self.debug_hook.take() {
+                    if hook.before_inst(self) {
+                        self.pause_pending = true;
+                    }
+                    self.debug_hook = Some(hook);
+                }
+            }
+            if self.pause_pending && base == 0 {
+                self.pause_pending = false;
+                self.resuming = true;
+                return Ok(Status::Paused);
+            }
+
+            if let Some(profiler) = &mut self.profiler {
+                profiler.count_inst();
+            }
+            if let Some(budget) = &mut self.budget {
+                if *budget == 0 {
+                    return Err(InkErr::BudgetExceeded);
+                }
+                *budget -= 1;
             }
 
             let frame = self.stack.last_mut().unwrap();
 
+            let trace_ip = frame.ip;
+            if let Some(coverage) = &mut self.coverage {
+                coverage.hit(frame.block_idx, frame.ip);
+            }
             let inst = &frame.block.code[frame.ip];
             let dest = inst.dest;
 
@@ -215,9 +459,12 @@ impl Vm {
                     match callee_fn {
                         Val::Func(callee_block_idx, heap_vals) => {
                             let callee_block = &self.prog[*callee_block_idx];
-                            let mut callee_frame = Frame::new(dest, callee_block.clone());
+                            let mut callee_frame =
+                                Frame::new(dest, *callee_block_idx, callee_block.clone());
 
-                            for (i, arg_reg) in arg_regs.iter().enumerate() {
+                            // extra arguments to a function are ignored
+                            let arg_count = arg_regs.len().min(callee_frame.regs.len());
+                            for (i, arg_reg) in arg_regs.iter().take(arg_count).enumerate() {
                                 callee_frame.regs[i] =
                                     frame.regs[*arg_reg].or_from_heap(&self.heap).clone();
                             }
@@ -234,10 +481,13 @@ impl Vm {
                             for (i, arg_reg) in arg_regs.iter().enumerate() {
                                 args[i] = frame.regs[*arg_reg].or_from_heap(&self.heap).clone();
                             }
-                            frame.regs[dest] = func(args)?;
+
+                            // native functions may re-enter the VM, so they are called
+                            // after this frame is no longer borrowed
+                            maybe_native_call = Some((func.clone(), args));
                         }
                         _ => {
-                            println!("Invalid fn: {:?}", callee_fn);
+                            eprintln!("Invalid fn: {:?}", callee_fn);
                             return Err(InkErr::InvalidFunctionCall);
                         }
                     }
@@ -266,11 +516,15 @@ impl Vm {
                     let cmp_a = &frame.regs[a_reg].or_from_heap(&self.heap);
                     let cmp_b = &frame.regs[b_reg].or_from_heap(&self.heap);
                     if cmp_a.eq(&cmp_b) {
+                        if let Some(coverage) = &mut self.coverage {
+                            coverage.take(frame.block_idx, frame.ip);
+                        }
                         let callee_fn = frame.regs[f_reg].or_from_heap(&self.heap);
                         match callee_fn {
                             Val::Func(callee_block_idx, heap_vals) => {
                                 let callee_block = &self.prog[*callee_block_idx];
-                                let mut callee_frame = Frame::new(dest, callee_block.clone());
+                                let mut callee_frame =
+                                    Frame::new(dest, *callee_block_idx, callee_block.clone());
 
                                 for (i, val) in heap_vals.iter().enumerate() {
                                     callee_frame.binds[i] = val.clone();
@@ -280,7 +534,7 @@ impl Vm {
                                 maybe_callee_frame = Some(callee_frame);
                             }
                             _ => {
-                                println!(
+                                eprintln!(
                                     "CALL_IF_EQ jump point is not a function: {:?}",
                                     callee_fn
                                 );
@@ -330,17 +584,54 @@ impl Vm {
 
             frame.ip += 1;
 
+            if let Some((func, args)) = maybe_native_call {
+                let caller_node = self.stack.last().unwrap().profile_node;
+                if self.profiler.is_some() {
+                    self.profile_call(caller_node, Site::Native(func.name.clone()));
+                }
+                let ret_val = func.call(self, args)?;
+                self.profile_enter(caller_node);
+                self.stack.last_mut().unwrap().regs[dest] = ret_val;
+            }
+
+            if let Some(tracer) = &mut self.tracer {
+                let frame = self.stack.last().unwrap();
+                if tracer.traces(frame.block_idx, &frame.block.name) {
+                    tracer.record(&TraceEvent {
+                        block_idx: frame.block_idx,
+                        block_name: &frame.block.name,
+                        ip: trace_ip,
+                        depth: self.stack.len(),
+                        inst: &frame.block.code[trace_ip],
+                        result: match maybe_callee_frame {
+                            Some(_) => None,
+                            None => Some(frame.regs[dest].or_from_heap(&self.heap)),
+                        },
+                    });
+                }
+            }
+
             match maybe_callee_frame {
                 Some(mut callee_frame) => {
-                    while self.should_pop_frame() {
+                    // tail calls replace the frames they return into, so the callee's
+                    // stack in the profiler is under the deepest frame that remains.
+                    let mut caller_node = self.stack.last().unwrap().profile_node;
+                    while self.should_pop_frame(base) {
                         // carry over return pointer
                         let top_frame = self.stack.pop().unwrap();
                         callee_frame.rp = top_frame.rp;
+                        if let Some(profiler) = &self.profiler {
+                            caller_node = profiler.parent(caller_node);
+                        }
                     }
-                    self.stack.push(callee_frame);
+                    callee_frame.profile_node =
+                        self.profile_call(caller_node, Site::Block(callee_frame.block_idx));
+                    self.push_frame(callee_frame);
                 }
                 None => {
-                    while self.should_pop_frame() {
+                    let mut returned = false;
+                    while self.should_pop_frame(base) {
+                        returned = true;
                         // prepare return
                         let top_frame = self.stack.last().unwrap();
 
@@ -349,15 +640,63 @@ impl Vm {
                         let ret_val = top_frame.regs[ret_reg].clone();
                         self.stack.pop();
 
-                        match self.stack.last_mut() {
-                            Some(frame) => frame.regs[rp] = ret_val,
-                            None => return Ok(ret_val.or_from_heap(&self.heap).clone()),
+                        if self.stack.len() == base {
+                            return Ok(Status::Exited(ret_val.or_from_heap(&self.heap).clone()));
                         }
+                        self.stack.last_mut().unwrap().regs[rp] = ret_val;
+                    }
+                    if returned {
+                        let node = self.stack.last().unwrap().profile_node;
+                        self.profile_enter(node);
                     }
                 }
             }
         }
 
-        return Ok(Val::Null);
+        return Ok(Status::Exited(Val::Null));
+    }
+}
+
+impl VmContext for Vm {
+    fn call(&mut self, func: &Val, args: Vec<Val>) -> Result<Val, InkErr> {
+        return match func {
+            Val::Func(callee_block_idx, heap_vals) => {
+                let callee_block = match self.prog.get(*callee_block_idx) {
+                    Some(block) => block,
+                    None => return Err(InkErr::InvalidFunctionCall),
+                };
+                let mut callee_frame = Frame::new(0, *callee_block_idx, callee_block.clone());
+
+                for (i, arg) in args.into_iter().enumerate().take(callee_frame.regs.len()) {
+                    callee_frame.regs[i] = arg;
+                }
+                for (i, val) in heap_vals.iter().enumerate() {
+                    callee_frame.binds[i] = val.clone();
+                }
+
+                let caller_node = match &self.profiler {
+                    Some(profiler) => profiler.current(),
+                    None => profile::ROOT,
+                };
+                callee_frame.profile_node =
+                    self.profile_call(caller_node, Site::Block(*callee_block_idx));
+
+                let base = self.stack.len();
+                self.push_frame(callee_frame);
+                let result = self.execute(base);
+                // a call that fails leaves its frames on the stack, which a
+                // paused program would otherwise resume into
+                if result.is_err() {
+                    self.stack.truncate(base);
+                }
+                self.profile_enter(caller_node);
+                match result? {
+                    Status::Exited(val) => Ok(val),
+                    Status::Paused => unreachable!("nested executions are never paused"),
+                }
+            }
+            Val::NativeFunc(func) => func.call(self, args),
+            _ => Err(InkErr::InvalidFunctionCall),
+        };
     }
 }
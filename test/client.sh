@@ -0,0 +1,11 @@
+#!/bin/sh
+# Scripted client for the schrift dap and lsp servers. Runs the given server
+# command, sends it each line of stdin as a Content-Length framed message, and
+# prints every message the server sends back on its own line, without the
+# framing.
+#
+#     test/client.sh ./target/debug/schrift dap < test/dap/007.requests
+
+while IFS= read -r req; do
+	printf 'Content-Length: %d\r\n\r\n%s' "$(printf '%s' "$req" | wc -c)" "$req"
+done | "$@" | sed -e 's/Content-Length: [0-9]*\r$//' -e 's/\r$//' | awk 'NF'
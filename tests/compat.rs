@@ -0,0 +1,36 @@
+// Compatibility suite. Every program in test/compat has a golden file with the
+// output Schrift recorded for it, which pins down the behaviour the suite
+// covers. Each program is compiled and run in its own VM, and every
+// divergence is reported before the test fails.
+
+use std::path::Path;
+
+use schrift::golden::{self, Outcome};
+
+#[test]
+fn compat() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/compat");
+    let programs = golden::discover(&dir).expect("could not read test/compat");
+    assert!(!programs.is_empty(), "no programs in test/compat");
+
+    let mut divergences = Vec::<String>::new();
+    for program in programs.iter() {
+        match golden::check(program, false) {
+            Ok(Outcome::Pass) => (),
+            Ok(Outcome::Fail(diff)) => {
+                divergences.push(format!("{} diverges:\n{}", program.display(), diff))
+            }
+            Ok(_) => divergences.push(format!("{} has no golden file", program.display())),
+            Err(e) => divergences.push(format!("{}: {:?}", program.display(), e)),
+        }
+    }
+
+    if !divergences.is_empty() {
+        panic!(
+            "{} of {} programs diverge from the reference interpreter\n\n{}",
+            divergences.len(),
+            programs.len(),
+            divergences.join("\n")
+        );
+    }
+}
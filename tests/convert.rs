@@ -0,0 +1,97 @@
+// Conversions between Ink values and Rust types. Every type that converts
+// into a Val converts back out of it unchanged, and converting a value of the
+// wrong kind is an error rather than a panic.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use schrift::comp::Comp;
+use schrift::err::InkErr;
+use schrift::val::Val;
+
+fn round_trip<T>(value: T) -> T
+where
+    T: Into<Val> + TryFrom<Val, Error = InkErr>,
+{
+    return T::try_from(value.into()).expect("could not convert back from Val");
+}
+
+#[test]
+fn primitives() {
+    assert_eq!(round_trip(4.5), 4.5);
+    assert!(round_trip(true));
+    assert_eq!(round_trip("ink".to_string()), "ink");
+    assert_eq!(round_trip(vec![0u8, 159, 255]), vec![0u8, 159, 255]);
+    assert_eq!(String::try_from(Val::from("schrift")).unwrap(), "schrift");
+}
+
+#[test]
+fn byte_strings_are_strings() {
+    assert!(matches!(Val::from(vec![1u8, 2]), Val::Str(_)));
+    assert!(matches!(
+        String::try_from(Val::from(vec![0xffu8])),
+        Err(InkErr::ExpectedString)
+    ));
+}
+
+#[test]
+fn lists() {
+    assert_eq!(round_trip(vec![1.0, 2.0, 3.0]), vec![1.0, 2.0, 3.0]);
+    assert_eq!(round_trip(Vec::<f64>::new()), Vec::<f64>::new());
+    assert_eq!(
+        round_trip(vec![vec!["a".to_string()], vec![], vec!["b".to_string()]]),
+        vec![vec!["a".to_string()], vec![], vec!["b".to_string()]]
+    );
+
+    // a list is a composite keyed by contiguous integers from 0
+    let comp = Comp::try_from(Val::from(vec![true, false])).unwrap();
+    assert_eq!(comp.len(), 2);
+    assert!(matches!(comp.get(&Val::Number(1.0)), Val::Bool(false)));
+}
+
+#[test]
+fn maps() {
+    let mut entries = HashMap::new();
+    entries.insert("one".to_string(), 1.0);
+    entries.insert("two".to_string(), 2.0);
+    assert_eq!(round_trip(entries.clone()), entries);
+
+    let mut nested = HashMap::new();
+    nested.insert("list".to_string(), vec![entries.clone()]);
+    assert_eq!(round_trip(nested.clone()), nested);
+}
+
+#[test]
+fn composites_that_are_not_lists() {
+    let mut comp = Comp::new();
+    comp.set(&Val::Number(0.0), Val::Number(1.0));
+    comp.set(&Val::Number(2.0), Val::Number(2.0));
+    assert!(matches!(
+        Vec::<f64>::try_from(Val::from(comp)),
+        Err(InkErr::ExpectedList)
+    ));
+}
+
+#[test]
+fn wrong_kinds() {
+    assert!(matches!(
+        f64::try_from(Val::from("1")),
+        Err(InkErr::ExpectedNumber)
+    ));
+    assert!(matches!(
+        bool::try_from(Val::Null),
+        Err(InkErr::ExpectedBoolean)
+    ));
+    assert!(matches!(
+        String::try_from(Val::Number(1.0)),
+        Err(InkErr::ExpectedString)
+    ));
+    assert!(matches!(
+        Comp::try_from(Val::Bool(true)),
+        Err(InkErr::ExpectedCompositeValue)
+    ));
+    assert!(matches!(
+        Vec::<f64>::try_from(Val::from(vec!["a"])),
+        Err(InkErr::ExpectedNumber)
+    ));
+}
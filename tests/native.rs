@@ -0,0 +1,77 @@
+// Native functions given to the compiler by a host program. They are closures
+// that may hold on to the host's state, and may call back into Ink, which may
+// in turn call them again before they return.
+
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use schrift::analyze;
+use schrift::err::InkErr;
+use schrift::gen;
+use schrift::lex;
+use schrift::parse;
+use schrift::val::{NativeFn, Val};
+use schrift::vm::{Vm, VmContext};
+
+// run compiles and runs prog with the given builtins, and returns the value of
+// its last expression
+fn run(prog: &str, builtins: Vec<NativeFn>) -> Result<Val, InkErr> {
+    let mut nodes = parse::parse(lex::tokenize(prog)?)?;
+    let names: Vec<String> = builtins
+        .iter()
+        .map(|builtin| builtin.name.clone())
+        .collect();
+    analyze::analyze(&mut nodes, &names)?;
+    let blocks = gen::generate(nodes, builtins)?;
+    return Vm::new(blocks).run();
+}
+
+// count is a builtin that counts how many times it was called, and returns
+// the count so far
+fn count(counter: &Rc<Cell<u32>>) -> NativeFn {
+    let counter = Rc::clone(counter);
+    return NativeFn::new("count", move |_ctx: &mut dyn VmContext, _args| {
+        counter.set(counter.get() + 1);
+        return Ok(Val::Number(counter.get() as f64));
+    });
+}
+
+// twice is a builtin that calls its argument twice, and returns what it
+// returned the second time
+fn twice() -> NativeFn {
+    return NativeFn::new("twice", |ctx: &mut dyn VmContext, args: Vec<Val>| {
+        ctx.call(&args[0], vec![])?;
+        return ctx.call(&args[0], vec![]);
+    });
+}
+
+#[test]
+fn closures_keep_host_state() {
+    let counter = Rc::new(Cell::new(0));
+    let last = run("count()\ncount()\ncount()", vec![count(&counter)]).unwrap();
+    assert_eq!(f64::try_from(last).unwrap(), 3.0);
+    assert_eq!(counter.get(), 3);
+}
+
+#[test]
+fn natives_called_back_into() {
+    let counter = Rc::new(Cell::new(0));
+    let prog = "
+count()
+twice(() => count())
+twice(() => twice(() => count()))
+";
+    let last = run(prog, vec![count(&counter), twice()]).unwrap();
+    assert_eq!(f64::try_from(last).unwrap(), 7.0);
+    assert_eq!(counter.get(), 7);
+
+    // the host sees calls made before an error, and none after it
+    let counter = Rc::new(Cell::new(0));
+    let failed = run(
+        "twice(() => count() + true)\ncount()",
+        vec![count(&counter), twice()],
+    );
+    assert!(failed.is_err());
+    assert_eq!(counter.get(), 1);
+}
@@ -0,0 +1,142 @@
+// serde support, which is only built with `--features serde`. Values are
+// serialized into Ink values and deserialized back, including through the
+// representations Ink has no type for, like enums and maps keyed by numbers.
+
+#![cfg(feature = "serde")]
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use schrift::comp::Comp;
+use schrift::err::InkErr;
+use schrift::ser::{from_val, to_val};
+use schrift::val::Val;
+
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T) {
+    let val = to_val(&value).expect("could not serialize");
+    let back: T = from_val(val).expect("could not deserialize");
+    assert_eq!(back, value);
+}
+
+#[test]
+fn primitives() {
+    round_trip(true);
+    round_trip(42u8);
+    round_trip(-7i64);
+    round_trip(2.5f64);
+    round_trip('x');
+    round_trip("schrift".to_string());
+    round_trip(());
+}
+
+#[test]
+fn options_and_sequences() {
+    round_trip(Some(3i32));
+    round_trip(None::<i32>);
+    round_trip(vec![1u16, 2, 3]);
+    round_trip((1u8, "two".to_string(), false));
+    round_trip(vec![vec![0.5f64], vec![]]);
+}
+
+#[test]
+fn string_keyed_maps() {
+    let mut map = HashMap::new();
+    map.insert("a".to_string(), vec![1i32]);
+    map.insert("b".to_string(), vec![]);
+    round_trip(map);
+}
+
+#[test]
+fn number_and_boolean_keyed_maps() {
+    let mut by_u32 = HashMap::new();
+    by_u32.insert(1u32, "one".to_string());
+    by_u32.insert(20u32, "twenty".to_string());
+    round_trip(by_u32);
+
+    let mut by_i64 = BTreeMap::new();
+    by_i64.insert(-3i64, 1.5f64);
+    round_trip(by_i64);
+
+    let mut by_bool = HashMap::new();
+    by_bool.insert(true, 1u8);
+    by_bool.insert(false, 0u8);
+    round_trip(by_bool);
+
+    // keys of the wrong type are an error, not a panic
+    let mut comp = Comp::new();
+    comp.set(&Val::from("one"), Val::Number(1.0));
+    let by_name: Result<HashMap<u32, f64>, InkErr> = from_val(Val::from(comp));
+    assert!(matches!(by_name, Err(InkErr::SerdeError(_))));
+}
+
+#[test]
+fn enums() {
+    round_trip(Ok::<u8, String>(1));
+    round_trip(Err::<u8, String>("failed".to_string()));
+}
+
+#[test]
+fn ink_values() {
+    let val = to_val(&vec!["a", "b"]).unwrap();
+    let items: Vec<String> = from_val(val).unwrap();
+    assert_eq!(items, vec!["a".to_string(), "b".to_string()]);
+
+    let fraction: Result<i32, InkErr> = from_val(Val::Number(1.5));
+    assert!(matches!(fraction, Err(InkErr::SerdeError(_))));
+    let too_big: Result<u8, InkErr> = from_val(Val::Number(300.0));
+    assert!(matches!(too_big, Err(InkErr::SerdeError(_))));
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+enum Shape {
+    Point,
+    Circle(f64),
+    Rect { width: f64, height: f64 },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Layer {
+    name: String,
+    shapes: Vec<Shape>,
+    parent: Option<Box<Layer>>,
+    tags: HashMap<String, Vec<u32>>,
+}
+
+#[test]
+fn derived_structs() {
+    let mut tags = HashMap::new();
+    tags.insert("z".to_string(), vec![1, 2]);
+    tags.insert("empty".to_string(), vec![]);
+    round_trip(Layer {
+        name: "top".to_string(),
+        shapes: vec![
+            Shape::Point,
+            Shape::Circle(0.5),
+            Shape::Rect {
+                width: 2.0,
+                height: 3.0,
+            },
+        ],
+        parent: Some(Box::new(Layer {
+            name: "base".to_string(),
+            shapes: vec![],
+            parent: None,
+            tags: HashMap::new(),
+        })),
+        tags,
+    });
+
+    // a composite without one of the struct's fields names the missing field
+    let mut comp = Comp::new();
+    comp.set(&Val::from("name"), Val::from("top"));
+    comp.set(&Val::from("shapes"), Val::from(Comp::new()));
+    comp.set(&Val::from("parent"), Val::Null);
+    let layer: Result<Layer, InkErr> = from_val(Val::from(comp));
+    match layer {
+        Err(InkErr::SerdeError(msg)) => assert_eq!(msg, "missing field `tags`"),
+        other => panic!("expected a missing field error, got {:?}", other),
+    }
+}